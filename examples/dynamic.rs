@@ -18,8 +18,8 @@ use bevy::{
     utils::hashbrown::HashMap,
 };
 use boimp::{
-    render::DummyIndicesImage, GridMode, Imposter, ImposterBakeCamera, ImposterBakePlugin,
-    ImposterData,
+    GridMode, Imposter, ImposterBakeCamera, ImposterBakeGizmoPlugin, ImposterBakeGizmos,
+    ImposterBakePlugin, ImposterLoaderSettings,
 };
 use camera_controller::{CameraController, CameraControllerPlugin};
 use rand::{thread_rng, Rng};
@@ -39,7 +39,7 @@ struct BakeSettings {
 
 fn main() {
     println!(
-        "press I to start baking every frame and spawn some imposters. press O to stop baking."
+        "press I to start baking every frame and spawn some imposters. press O to stop baking. press G to toggle bake gizmos."
     );
 
     App::new()
@@ -57,6 +57,7 @@ fn main() {
             }),
             CameraControllerPlugin,
             ImposterBakePlugin,
+            ImposterBakeGizmoPlugin,
         ))
         .add_plugins((FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin::default()))
         .add_systems(Startup, setup)
@@ -70,6 +71,7 @@ fn main() {
                 rotate,
                 swap_old,
                 setup_anim_after_load,
+                toggle_gizmos,
             ),
         )
         .run();
@@ -228,6 +230,7 @@ fn scene_load_check(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn setup_anim_after_load(
     mut setup: Local<bool>,
     mut players: Query<&mut AnimationPlayer>,
@@ -425,6 +428,7 @@ fn setup_scene_after_load(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn impost(
     mut commands: Commands,
     k: Res<ButtonInput<KeyCode>>,
@@ -434,7 +438,6 @@ fn impost(
     mut materials: ResMut<Assets<Imposter>>,
     cams: Query<Entity, With<ImposterBakeCamera>>,
     settings: Res<BakeSettings>,
-    dummy_indices: Res<DummyIndicesImage>,
 ) {
     if k.just_pressed(KeyCode::KeyO) {
         for entity in cams.iter() {
@@ -455,6 +458,15 @@ fn impost(
             ..Default::default()
         };
         camera.init_target(&mut images);
+        // shows the target as it's baked
+        let material = materials.add(
+            Imposter::from_bake(&camera)
+                .with_settings(ImposterLoaderSettings {
+                    multisample: settings.multisample_target,
+                    ..default()
+                })
+                .build(&mut images),
+        );
 
         let mut rng = thread_rng();
         let range = scene_handle.sphere.radius * (settings.count as f32).sqrt();
@@ -487,24 +499,7 @@ fn impost(
                         rotation.y,
                         rotation.z,
                     )),
-                MeshMaterial3d(materials.add(Imposter {
-                    data: ImposterData::new(
-                        Vec3::ZERO,
-                        scene_handle.sphere.radius,
-                        settings.grid_size,
-                        settings.tile_size,
-                        UVec2::ZERO,
-                        UVec2::splat(settings.tile_size),
-                        settings.mode,
-                        settings.multisample_target,
-                        false,
-                        1.0,
-                    ),
-                    pixels: camera.target.clone().unwrap(),
-                    indices: dummy_indices.0.clone(),
-                    alpha_mode: AlphaMode::Blend,
-                    vram_bytes: 0,
-                })),
+                MeshMaterial3d(material.clone()),
                 RenderLayers::layer(1),
             ));
         }
@@ -552,6 +547,13 @@ fn rotate(mut q: Query<&mut Transform, With<Rotate>>, time: Res<Time>) {
     }
 }
 
+fn toggle_gizmos(key_input: Res<ButtonInput<KeyCode>>, mut config_store: ResMut<GizmoConfigStore>) {
+    let (config, _) = config_store.config_mut::<ImposterBakeGizmos>();
+    if key_input.just_pressed(KeyCode::KeyG) {
+        config.enabled = !config.enabled;
+    }
+}
+
 fn swap_old(key_input: Res<ButtonInput<KeyCode>>, mut imps: ResMut<Assets<Imposter>>) {
     if key_input.just_pressed(KeyCode::KeyP) {
         for a in imps.iter_mut() {
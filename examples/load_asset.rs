@@ -1,7 +1,10 @@
 // load and display a saved imposter
 
 use bevy::{asset::LoadState, prelude::*};
-use boimp::{Imposter, ImposterLoaderSettings, ImposterRenderPlugin};
+use boimp::{
+    BillboardMode, Imposter, ImposterDebugMode, ImposterLoaderSettings, ImposterRenderPlugin,
+    TileBlendMode,
+};
 use camera_controller::{CameraController, CameraControllerPlugin};
 
 #[path = "helpers/camera_controller.rs"]
@@ -13,7 +16,7 @@ pub fn main() {
         .add_plugins(ImposterRenderPlugin)
         .add_plugins(CameraControllerPlugin)
         .add_systems(Startup, setup)
-        .add_systems(Update, set_camera_pos)
+        .add_systems(Update, (set_camera_pos, spawn_variant, cycle_debug_mode))
         .run();
 }
 
@@ -24,9 +27,24 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut meshes: Res
         .value_from_str("--source")
         .unwrap_or("boimps/output.boimp".to_owned());
     let multisample = args.contains("--multisample");
+    let write_depth = args.contains("--write-depth");
+    let parallax_steps = args.value_from_str("--parallax-steps").unwrap_or(0);
+    let vertex_tiles = args.contains("--vertex-tiles");
+    let wind = args.contains("--wind");
+    let variant = args.contains("--variant");
+    let billboard_mode = if args.contains("--axis-locked") {
+        BillboardMode::AxisLocked
+    } else {
+        BillboardMode::Spherical
+    };
+    let blend_mode = match args.value_from_str::<_, String>("--blend").as_deref() {
+        Ok("n") | Ok("nearest") => TileBlendMode::Nearest,
+        Ok("2") | Ok("two") => TileBlendMode::TwoTile,
+        _ => TileBlendMode::ThreeTile,
+    };
 
     if !args.finish().is_empty() {
-        println!("args: --source <file>\n--multisample (to multisample)");
+        println!("args: --source <file>\n--multisample (to multisample)\n--write-depth (to write per-pixel depth)\n--parallax-steps <n> (to ray-march the baked depth)\n--vertex-tiles (to select tiles per vertex)\n--blend [n]earest | two | three (tiles to blend)\n--wind (to sway in the wind)\n--axis-locked (to only turn the quad around the up axis)\n--variant (to show a recolored copy, for indexed assets)\n\npress G to cycle debug views");
         std::process::exit(1);
     };
 
@@ -35,6 +53,13 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut meshes: Res
         MeshMaterial3d(
             asset_server.load_with_settings::<Imposter, ImposterLoaderSettings>(source, move |s| {
                 s.multisample = multisample;
+                s.write_depth = write_depth;
+                s.parallax_steps = parallax_steps;
+                s.vertex_tiles = vertex_tiles;
+                s.blend_mode = blend_mode;
+                s.wind = wind;
+                s.billboard_mode = billboard_mode;
+                s.keep_palette = variant;
             }),
         ),
     ));
@@ -49,6 +74,62 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>, mut meshes: Res
     ));
 
     commands.spawn(DirectionalLight::default());
+    commands.insert_resource(ShowVariant(variant));
+}
+
+#[derive(Resource)]
+struct ShowVariant(bool);
+
+fn cycle_debug_mode(k: Res<ButtonInput<KeyCode>>, mut mode: ResMut<ImposterDebugMode>) {
+    if k.just_pressed(KeyCode::KeyG) {
+        *mode = match *mode {
+            ImposterDebugMode::None => ImposterDebugMode::TileIndices,
+            ImposterDebugMode::TileIndices => ImposterDebugMode::BlendWeights,
+            ImposterDebugMode::BlendWeights => ImposterDebugMode::Depth,
+            ImposterDebugMode::Depth => ImposterDebugMode::Normals,
+            ImposterDebugMode::Normals => ImposterDebugMode::PackedBounds,
+            ImposterDebugMode::PackedBounds => ImposterDebugMode::Storage,
+            ImposterDebugMode::Storage => ImposterDebugMode::DepthDiscard,
+            ImposterDebugMode::DepthDiscard => ImposterDebugMode::None,
+        };
+        println!("debug mode: {:?}", *mode);
+    }
+}
+
+// spawn a hue-shifted copy sharing the original's indices
+fn spawn_variant(
+    mut commands: Commands,
+    mut show: ResMut<ShowVariant>,
+    handles: Query<(&MeshMaterial3d<Imposter>, &Mesh3d)>,
+    mut imposters: ResMut<Assets<Imposter>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    if !show.0 {
+        return;
+    }
+
+    let Some((handle, mesh)) = handles.iter().next() else {
+        return;
+    };
+    let Some(imposter) = imposters.get(handle.id()) else {
+        return;
+    };
+    show.0 = false;
+
+    let Some(variant) = imposter.palette_variant(&mut images, |props| {
+        let color = Hsla::from(LinearRgba::from_vec4(props.rgba)).rotate_hue(-60.0);
+        props.rgba = LinearRgba::from(color).to_vec4();
+    }) else {
+        warn!("palette variants need an indexed asset");
+        return;
+    };
+    let offset = imposter.data.center_and_scale.w * 2.0;
+
+    commands.spawn((
+        mesh.clone(),
+        MeshMaterial3d(imposters.add(variant)),
+        Transform::from_translation(Vec3::Z * offset),
+    ));
 }
 
 fn set_camera_pos(
@@ -1,14 +1,11 @@
 // spawn a gltf and create an imposter from it
 // scene mgmt copied wholesale from bevy
 
-use bevy::{
-    asset::LoadState,
-    prelude::*,
-    render::primitives::{Aabb, Sphere},
-    scene::InstanceId,
-    window::ExitCondition,
+use bevy::{asset::LoadState, prelude::*, scene::InstanceId, window::ExitCondition};
+use boimp::{
+    bake::BakeTimeout, GridMode, ImposterBakeCamera, ImposterBakeFailed, ImposterBakePlugin,
+    ImposterBakeProgress, ImposterBakeTarget, ImposterBakeTargetFitted,
 };
-use boimp::{GridMode, ImposterBakeCamera, ImposterBakePlugin};
 
 #[derive(Resource)]
 struct BakeSettings {
@@ -19,6 +16,8 @@ struct BakeSettings {
     output: String,
     shrink_asset: bool,
     index_asset: bool,
+    filterable: bool,
+    compress: bool,
 }
 
 fn main() {
@@ -37,7 +36,10 @@ fn main() {
         ))
         .add_systems(Startup, setup)
         .add_systems(PreUpdate, setup_scene_after_load)
-        .add_systems(Update, scene_load_check)
+        .add_systems(
+            Update,
+            (scene_load_check, set_save_callback, report_progress),
+        )
         .run();
 }
 
@@ -63,7 +65,7 @@ pub struct SceneHandle {
     instance_id: Option<InstanceId>,
     pub is_loaded: bool,
     pub has_light: bool,
-    pub sphere: Sphere,
+    pub root: Option<Entity>,
 }
 
 impl SceneHandle {
@@ -74,7 +76,7 @@ impl SceneHandle {
             instance_id: None,
             is_loaded: false,
             has_light: false,
-            sphere: Sphere::default(),
+            root: None,
         }
     }
 }
@@ -109,11 +111,13 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 
     let shrink_asset = !args.contains("--no-shrink");
     let index_asset = !args.contains("--no-index");
+    let filterable = args.contains("--filterable");
+    let compress = args.contains("--compress");
 
     let unused = args.finish();
     if !unused.is_empty() {
         println!("unrecognized arguments: {unused:?}");
-        println!("args: \n--mode [h]emispherical or [s]pherical\n--grid n (grid size, default 8)\n--tile n (tile size, default 128)\n--multisample-source <n> (average over a larger set of samples, default 8)\n--source path (asset to load, default flight helmet)\n--no-shrink (don't pack the output asset)\n--no-index (don't index the output asset)");
+        println!("args: \n--mode [h]emispherical or [s]pherical\n--grid n (grid size, default 8)\n--tile n (tile size, default 128)\n--multisample-source <n> (average over a larger set of samples, default 8)\n--source path (asset to load, default flight helmet)\n--no-shrink (don't pack the output asset)\n--no-index (don't index the output asset)\n--filterable (store hardware-filterable textures)\n--compress (block compress filterable textures)");
         std::process::exit(1);
     }
 
@@ -130,6 +134,8 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         output,
         shrink_asset,
         index_asset,
+        filterable,
+        compress,
     });
 }
 
@@ -181,6 +187,7 @@ fn scene_load_check(
                         Visibility::default(),
                     ))
                     .id();
+                scene_handle.root = Some(root);
                 scene_handle.instance_id =
                     Some(scene_spawner.spawn_as_child(gltf_scene_handle.clone_weak(), root));
 
@@ -205,64 +212,54 @@ fn scene_load_check(
 fn setup_scene_after_load(
     mut commands: Commands,
     mut setup: Local<bool>,
-    mut scene_handle: ResMut<SceneHandle>,
-    meshes: Query<(&GlobalTransform, Option<&Aabb>), With<Mesh3d>>,
-    scene_spawner: Res<SceneSpawner>,
+    scene_handle: Res<SceneHandle>,
     settings: Res<BakeSettings>,
 ) {
     if scene_handle.is_loaded && !*setup {
         *setup = true;
-        // Find an approximate bounding box of the scene from its meshes
-        if meshes.iter().any(|(_, maybe_aabb)| maybe_aabb.is_none()) {
-            return;
-        }
-
-        let mut points = Vec::default();
-        for entity in scene_spawner.iter_instance_entities(scene_handle.instance_id.unwrap()) {
-            let Ok((transform, maybe_aabb)) = meshes.get(entity) else {
-                continue;
-            };
-
-            let aabb = maybe_aabb.unwrap();
-            let corners = [
-                Vec3::new(-1.0, -1.0, -1.0),
-                Vec3::new(-1.0, -1.0, 1.0),
-                Vec3::new(-1.0, 1.0, -1.0),
-                Vec3::new(-1.0, 1.0, 1.0),
-                Vec3::new(1.0, -1.0, -1.0),
-                Vec3::new(1.0, -1.0, 1.0),
-                Vec3::new(1.0, 1.0, -1.0),
-                Vec3::new(1.0, 1.0, 1.0),
-            ];
-            points.extend(corners.iter().map(|c| {
-                transform
-                    .transform_point(Vec3::from(aabb.center) + (Vec3::from(aabb.half_extents) * *c))
-            }));
-        }
-
-        let aabb = Aabb::enclosing(points).unwrap();
-        let sphere = Sphere {
-            center: aabb.center,
-            radius: aabb.half_extents.length(),
-        };
-        info!("sphere: {:?}", sphere);
-        scene_handle.sphere = sphere;
 
         info!("running imposter baking");
-        let mut camera = ImposterBakeCamera {
-            radius: scene_handle.sphere.radius,
+        let camera = ImposterBakeCamera {
             grid_size: settings.grid_size,
             tile_size: settings.tile_size,
             grid_mode: settings.mode,
             continuous: false,
             multisample: settings.multisample,
+            // don't hang if something in the scene never renders
+            timeout: Some(BakeTimeout::Seconds(60.0)),
             ..Default::default()
         };
-        let save_callback = camera.save_asset_callback(
-            &settings.output,
-            settings.shrink_asset,
-            settings.index_asset,
-        );
+
+        // fit the capture sphere to the scene's meshes
+        commands.spawn((camera, ImposterBakeTarget::new(scene_handle.root.unwrap())));
+    }
+}
+
+// the save callbacks capture the radius, so wait for the fitted bounds
+fn set_save_callback(
+    mut fitted: EventReader<ImposterBakeTargetFitted>,
+    mut cameras: Query<&mut ImposterBakeCamera>,
+    settings: Res<BakeSettings>,
+) {
+    for event in fitted.read() {
+        info!("sphere: center {}, radius {}", event.center, event.radius);
+        let Ok(mut camera) = cameras.get_mut(event.camera) else {
+            continue;
+        };
+
+        let save_callback: Box<dyn FnOnce(Image) + Send + Sync> = if settings.filterable {
+            Box::new(camera.save_filterable_asset_callback(
+                &settings.output,
+                settings.shrink_asset,
+                settings.compress,
+            ))
+        } else {
+            Box::new(camera.save_asset_callback(
+                &settings.output,
+                settings.shrink_asset,
+                settings.index_asset,
+            ))
+        };
 
         let output = settings.output.clone();
         camera.set_callback(move |image| {
@@ -270,10 +267,18 @@ fn setup_scene_after_load(
             save_callback(image);
             std::process::exit(0);
         });
+    }
+}
 
-        commands.spawn((
-            camera,
-            Transform::from_translation(scene_handle.sphere.center.into()),
-        ));
+fn report_progress(
+    mut progress: EventReader<ImposterBakeProgress>,
+    mut failed: EventReader<ImposterBakeFailed>,
+) {
+    if let Some(progress) = progress.read().last() {
+        info!("baked {}/{} tiles", progress.rendered, progress.total);
+    }
+    if let Some(failed) = failed.read().next() {
+        error!("bake failed: {}", failed.reason);
+        std::process::exit(1);
     }
 }
@@ -1,6 +1,6 @@
 use core::str;
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::BTreeMap,
     io::{Cursor, Read, Write},
     path::PathBuf,
 };
@@ -8,6 +8,7 @@ use std::{
 use anyhow::anyhow;
 use bevy::{
     asset::{io::Reader, AssetLoader},
+    image::{ktx2_buffer_to_image, CompressedImageFormats},
     log::{debug, info},
     math::{UVec2, Vec3},
     prelude::{AlphaMode, Image},
@@ -15,16 +16,26 @@ use bevy::{
 };
 use image::{DynamicImage, ImageBuffer};
 use serde::{Deserialize, Serialize};
-use wgpu::{Extent3d, TextureFormat};
+use wgpu::{Extent3d, TextureFormat, TextureUsages};
 
 use crate::{
+    filterable::{filterable_ktx2_files, filterable_sampler, ALBEDO_FILE, NORMAL_FILE, PROPS_FILE},
+    material_props::tile_mips,
     oct_coords::GridMode,
-    render::{Imposter, ImposterData, INDEXED_FLAG, RENDER_MULTISAMPLE_FLAG},
+    render::{
+        BillboardMode, Imposter, ImposterData, TileBlendMode, FILTERABLE_FLAG,
+        IMPOSTER_WIND_BUFFER_HANDLE, INDEXED_FLAG, RENDER_MULTISAMPLE_FLAG, VERTEX_TILES_FLAG,
+        WIND_FLAG, WRITE_DEPTH_FLAG,
+    },
 };
 
-pub struct ImposterLoader;
+pub struct ImposterLoader {
+    // for the block compressed filterable storage
+    pub(crate) supported_compressed_formats: CompressedImageFormats,
+}
 
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct ImposterLoaderSettings {
     // smooth sample the material texture
     pub multisample: bool,
@@ -33,6 +44,20 @@ pub struct ImposterLoaderSettings {
     // roughly alpha mode. 0 -> Blend, 1 -> Opaque, (0-1) -> Mask
     // if you need more control you can modify the loaded asset (we can't put actual alpha mode here because it doesn't serialize)
     pub alpha_blend: f32,
+    // write per-pixel depth from the baked depth. always on in shadow maps and the deferred gbuffer
+    pub write_depth: bool,
+    // ray-march steps per tile, 0 -> single offset sample
+    pub parallax_steps: u32,
+    // select tiles per vertex rather than per fragment
+    pub vertex_tiles: bool,
+    // number of tiles blended per pixel
+    pub blend_mode: TileBlendMode,
+    // sway with the `ImposterWind` resource
+    pub wind: bool,
+    // quad orientation
+    pub billboard_mode: BillboardMode,
+    // keep the indexed palette in the main world as well, for `Imposter::palette_variant`
+    pub keep_palette: bool,
 }
 
 impl Default for ImposterLoaderSettings {
@@ -41,10 +66,26 @@ impl Default for ImposterLoaderSettings {
             multisample: Default::default(),
             alpha: 1.0,
             alpha_blend: 0.0,
+            write_depth: false,
+            parallax_steps: 0,
+            vertex_tiles: false,
+            blend_mode: TileBlendMode::ThreeTile,
+            wind: false,
+            billboard_mode: BillboardMode::Spherical,
+            keep_palette: false,
         }
     }
 }
 
+fn read_zip_file(
+    zip: &mut zip::ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let mut data = Vec::default();
+    zip.by_name(name)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
 impl AssetLoader for ImposterLoader {
     type Asset = Imposter;
 
@@ -65,10 +106,7 @@ impl AssetLoader for ImposterLoader {
             .map_err(|_| anyhow!("read failed"))?;
         let cursor = Cursor::new(&bytes[..]);
         let mut zip = zip::ZipArchive::new(cursor)?;
-        let settings = zip
-            .by_name("settings.txt")?
-            .bytes()
-            .collect::<Result<Vec<_>, _>>()?;
+        let settings = read_zip_file(&mut zip, "settings.txt")?;
         let mut parts = str::from_utf8(&settings)?.split(' ');
         let (
             Some(grid_size),
@@ -98,128 +136,84 @@ impl AssetLoader for ImposterLoader {
         let packed_tile_offset = UVec2::new(packed_offset_x.parse()?, packed_offset_y.parse()?);
         let packed_tile_size = UVec2::new(packed_size_x.parse()?, packed_size_y.parse()?);
 
+        let is_filterable = zip.file_names().any(|n| n == ALBEDO_FILE);
         let is_indexed = zip.file_names().any(|n| n == "pixels.png");
-        let (pixels_image, indices_image, vram_bytes) = if is_indexed {
-            let raw_pixels = zip
-                .by_name("pixels.png")?
-                .bytes()
-                .collect::<Result<Vec<_>, _>>()?;
+        let size: UVec2 = packed_tile_size * grid_size;
+        let mut filterable = None;
+        let (pixels_image, indices_image, vram_bytes) = if is_filterable {
+            let mut vram_bytes = 0;
+            let mut load_texture = |name: &str, is_srgb: bool| -> Result<_, anyhow::Error> {
+                let data = read_zip_file(&mut zip, name)?;
+                let mut image =
+                    ktx2_buffer_to_image(&data, self.supported_compressed_formats, is_srgb)?;
+                image.sampler = filterable_sampler();
+                image.asset_usage = RenderAssetUsages::RENDER_WORLD;
+                vram_bytes += image.data.len() as u32;
+                Ok(load_context.add_labeled_asset(name.to_owned(), image))
+            };
+            filterable = Some([
+                load_texture(ALBEDO_FILE, true)?,
+                load_texture(NORMAL_FILE, false)?,
+                load_texture(PROPS_FILE, false)?,
+            ]);
+
+            let pixels_image =
+                load_context.add_labeled_asset("dummy_pixels".to_owned(), dummy_uint_image());
+            let indices_image =
+                load_context.add_labeled_asset("dummy_indices".to_owned(), dummy_uint_image());
+            (pixels_image, indices_image, vram_bytes)
+        } else if is_indexed {
+            let raw_pixels = read_zip_file(&mut zip, "pixels.png")?;
             let mut reader = image::ImageReader::new(std::io::Cursor::new(raw_pixels));
             reader.set_format(image::ImageFormat::Png);
             reader.no_limits();
-            let pixels_bytes = reader.decode()?.into_bytes();
-            let pixels_x = (pixels_bytes.len() as f32 / 8.0).sqrt().ceil() as u32;
-            let pixels_y = (pixels_bytes.len() as f32 / (8 * pixels_x) as f32).ceil() as u32;
-            let pixels_image = Image::new(
-                Extent3d {
-                    width: pixels_x,
-                    height: pixels_y,
-                    depth_or_array_layers: 1,
-                },
-                wgpu::TextureDimension::D2,
-                pixels_bytes,
-                TextureFormat::Rg32Uint,
-                RenderAssetUsages::RENDER_WORLD,
-            );
-            let pixels_image = load_context.add_labeled_asset("pixels".to_owned(), pixels_image);
+            let palette = props_from_bytes(&reader.decode()?.into_bytes());
+            let loaded_u16 = palette.len() < 65536;
 
-            let raw_indices = zip
-                .by_name("indices.png")?
-                .bytes()
-                .collect::<Result<Vec<_>, _>>()?;
+            let raw_indices = read_zip_file(&mut zip, "indices.png")?;
             let mut reader = image::ImageReader::new(std::io::Cursor::new(raw_indices));
             reader.set_format(image::ImageFormat::Png);
             reader.no_limits();
             let indices_bytes = reader.decode()?.into_bytes();
+            let base_indices = decode_indices(&indices_bytes, size, loaded_u16);
 
-            let use_u16 = pixels_x * pixels_y < 65536;
-
-            let size: UVec2 = packed_tile_size * grid_size;
-            let width = if use_u16 { (size.x + 1) / 2 } else { size.x };
-            debug!(
-                "load use_u16? {use_u16}, base size: {}, use size: {}, height: {}, total pix: {}",
-                size.x,
-                width,
-                size.y,
-                indices_bytes.len()
-            );
-            let indices_image = Image::new(
-                Extent3d {
-                    width,
-                    height: size.y,
-                    depth_or_array_layers: 1,
-                },
-                wgpu::TextureDimension::D2,
-                indices_bytes,
-                TextureFormat::R32Uint,
-                RenderAssetUsages::RENDER_WORLD,
+            let (pixels_image, indices_image, vram_bytes) = indexed_images(
+                palette,
+                &base_indices,
+                grid_size,
+                packed_tile_size,
+                load_settings.keep_palette,
             );
+            let pixels_image = load_context.add_labeled_asset("pixels".to_owned(), pixels_image);
             let indices_image = load_context.add_labeled_asset("indices".to_owned(), indices_image);
-            (
-                pixels_image,
-                indices_image,
-                pixels_x * pixels_y * 8 + width * size.y * 4,
-            )
+            (pixels_image, indices_image, vram_bytes)
         } else {
-            let raw_image = zip
-                .by_name("texture.png")?
-                .bytes()
-                .collect::<Result<Vec<_>, _>>()?;
+            let raw_image = read_zip_file(&mut zip, "texture.png")?;
             let mut reader = image::ImageReader::new(std::io::Cursor::new(raw_image));
             reader.set_format(image::ImageFormat::Png);
             reader.no_limits();
             let pixels_bytes = reader.decode()?.into_bytes();
-            let size: UVec2 = packed_tile_size * grid_size;
-            let pixels_image = Image::new(
-                Extent3d {
-                    width: size.x,
-                    height: size.y,
-                    depth_or_array_layers: 1,
-                },
-                wgpu::TextureDimension::D2,
-                pixels_bytes,
-                TextureFormat::Rg32Uint,
-                RenderAssetUsages::RENDER_WORLD,
-            );
+            let (pixels_image, pixels_vram) = flat_image(pixels_bytes, grid_size, packed_tile_size);
             let pixels_image = load_context.add_labeled_asset("texture".to_owned(), pixels_image);
 
-            let indices_image = load_context.add_labeled_asset(
-                "dummy_indices".to_owned(),
-                Image::new(
-                    Extent3d {
-                        width: 1,
-                        height: 1,
-                        depth_or_array_layers: 1,
-                    },
-                    wgpu::TextureDimension::D2,
-                    vec![0, 0, 0, 0],
-                    TextureFormat::R32Uint,
-                    RenderAssetUsages::RENDER_WORLD,
-                ),
-            );
-
-            (pixels_image, indices_image, size.x * size.y * 8)
+            let indices_image =
+                load_context.add_labeled_asset("dummy_indices".to_owned(), dummy_uint_image());
+            (pixels_image, indices_image, pixels_vram)
         };
 
-        let flags = match load_settings.multisample {
-            true => RENDER_MULTISAMPLE_FLAG,
-            false => 0,
-        } + match mode {
+        let mode = match mode {
             "spherical" => GridMode::Spherical,
             "hemispherical" => GridMode::Hemispherical,
             "Horizontal" => GridMode::Horizontal,
             _ => anyhow::bail!("bad mode `{}`", mode),
-        }
-        .as_flags()
-            + if is_indexed { INDEXED_FLAG } else { 0 };
-
-        let alpha_mode = if load_settings.alpha_blend == 0.0 {
-            AlphaMode::Blend
-        } else if load_settings.alpha_blend == 1.0 {
-            AlphaMode::Opaque
-        } else {
-            AlphaMode::Mask(load_settings.alpha_blend)
         };
+        let flags = settings_flags(load_settings, mode)
+            + if is_indexed { INDEXED_FLAG } else { 0 }
+            + if is_filterable { FILTERABLE_FLAG } else { 0 };
+        let alpha_mode = settings_alpha_mode(load_settings);
+
+        let [albedo_texture, normal_texture, props_texture] =
+            filterable.map_or([None, None, None], |f| f.map(Some));
 
         Ok(Imposter {
             data: ImposterData {
@@ -227,12 +221,17 @@ impl AssetLoader for ImposterLoader {
                 grid_size,
                 flags,
                 alpha: load_settings.alpha,
+                parallax_steps: load_settings.parallax_steps,
                 base_tile_size,
                 packed_tile_offset,
                 packed_tile_size,
             },
             pixels: pixels_image,
             indices: indices_image,
+            albedo_texture,
+            normal_texture,
+            props_texture,
+            wind: IMPOSTER_WIND_BUFFER_HANDLE,
             alpha_mode,
             vram_bytes: vram_bytes as usize,
         })
@@ -243,15 +242,263 @@ impl AssetLoader for ImposterLoader {
     }
 }
 
+// render flags from the loader settings, excluding the storage flags
+pub(crate) fn settings_flags(settings: &ImposterLoaderSettings, mode: GridMode) -> u32 {
+    mode.as_flags()
+        + if settings.multisample {
+            RENDER_MULTISAMPLE_FLAG
+        } else {
+            0
+        }
+        + if settings.write_depth {
+            WRITE_DEPTH_FLAG
+        } else {
+            0
+        }
+        + if settings.vertex_tiles {
+            VERTEX_TILES_FLAG
+        } else {
+            0
+        }
+        + settings.blend_mode.as_flags()
+        + if settings.wind { WIND_FLAG } else { 0 }
+        + settings.billboard_mode.as_flags()
+}
+
+pub(crate) fn settings_alpha_mode(settings: &ImposterLoaderSettings) -> AlphaMode {
+    if settings.alpha_blend == 0.0 {
+        AlphaMode::Blend
+    } else if settings.alpha_blend == 1.0 {
+        AlphaMode::Opaque
+    } else {
+        AlphaMode::Mask(settings.alpha_blend)
+    }
+}
+
+// palette and indices textures (with tile mips) for indexed storage, and their vram size
+pub(crate) fn indexed_images(
+    mut palette: Vec<[u32; 2]>,
+    base_indices: &[u32],
+    grid_size: u32,
+    packed_tile_size: UVec2,
+    keep_palette: bool,
+) -> (Image, Image, u32) {
+    let size = packed_tile_size * grid_size;
+
+    // mip the expanded props, then add any new values to the palette
+    let base = base_indices
+        .iter()
+        .map(|ix| palette[*ix as usize])
+        .collect::<Vec<_>>();
+    let mips = tile_mips(&base, grid_size, packed_tile_size);
+    let mut lookup = BTreeMap::default();
+    for (ix, p) in palette.iter().enumerate() {
+        lookup.entry(*p).or_insert(ix as u32);
+    }
+    let mut mip_indices = Vec::with_capacity(mips.len());
+    for mip in &mips {
+        let indices = mip
+            .data
+            .iter()
+            .map(|p| {
+                *lookup.entry(*p).or_insert_with(|| {
+                    palette.push(*p);
+                    palette.len() as u32 - 1
+                })
+            })
+            .collect::<Vec<_>>();
+        mip_indices.push((mip.size(grid_size), indices));
+    }
+
+    let pixels_x = (palette.len() as f32).sqrt().ceil() as u32;
+    let pixels_y = (palette.len() as f32 / pixels_x as f32).ceil() as u32;
+    palette.resize((pixels_x * pixels_y) as usize, [0, 0]);
+    let use_u16 = pixels_x * pixels_y < 65536;
+
+    let mut pixels_image = Image::new(
+        Extent3d {
+            width: pixels_x,
+            height: pixels_y,
+            depth_or_array_layers: 1,
+        },
+        wgpu::TextureDimension::D2,
+        props_to_bytes(&palette),
+        TextureFormat::Rg32Uint,
+        if keep_palette {
+            RenderAssetUsages::all()
+        } else {
+            RenderAssetUsages::RENDER_WORLD
+        },
+    );
+    // allow copying into an `ImposterAtlas`
+    pixels_image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+
+    let (indices_bytes, width) = encode_indices(base_indices, size, use_u16);
+    debug!(
+        "load use_u16? {use_u16}, base size: {}, use size: {}, height: {}, mips: {}",
+        size.x,
+        width,
+        size.y,
+        mips.len()
+    );
+    let mut indices_image = Image::new(
+        Extent3d {
+            width,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        wgpu::TextureDimension::D2,
+        indices_bytes,
+        TextureFormat::R32Uint,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    for (level_size, indices) in mip_indices {
+        let (bytes, level_width) = encode_indices(&indices, level_size, use_u16);
+        // u16 pairs can round up past the texture's mip size, stop there
+        if !push_mip_level(&mut indices_image, bytes, level_width, 4) {
+            break;
+        }
+    }
+    indices_image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+    let vram_bytes = pixels_x * pixels_y * 8 + indices_image.data.len() as u32;
+    (pixels_image, indices_image, vram_bytes)
+}
+
+// material texture (with tile mips) for flat storage, and its vram size
+pub(crate) fn flat_image(
+    pixels_bytes: Vec<u8>,
+    grid_size: u32,
+    packed_tile_size: UVec2,
+) -> (Image, u32) {
+    let size = packed_tile_size * grid_size;
+    let mips = tile_mips(
+        &props_from_bytes(&pixels_bytes),
+        grid_size,
+        packed_tile_size,
+    );
+    let mut pixels_image = Image::new(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        wgpu::TextureDimension::D2,
+        pixels_bytes,
+        TextureFormat::Rg32Uint,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    for mip in mips {
+        let width = mip.size(grid_size).x;
+        push_mip_level(&mut pixels_image, props_to_bytes(&mip.data), width, 8);
+    }
+    pixels_image.texture_descriptor.usage |= TextureUsages::COPY_SRC;
+    let vram_bytes = pixels_image.data.len() as u32;
+    (pixels_image, vram_bytes)
+}
+
+// placeholder for unused uint texture bindings
+pub(crate) fn dummy_uint_image() -> Image {
+    Image::new(
+        Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        wgpu::TextureDimension::D2,
+        vec![0, 0, 0, 0],
+        TextureFormat::R32Uint,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+pub(crate) fn props_from_bytes(bytes: &[u8]) -> Vec<[u32; 2]> {
+    bytes
+        .chunks_exact(8)
+        .map(|c| {
+            [
+                u32::from_le_bytes(c[0..4].try_into().unwrap()),
+                u32::from_le_bytes(c[4..8].try_into().unwrap()),
+            ]
+        })
+        .collect()
+}
+
+fn props_to_bytes(props: &[[u32; 2]]) -> Vec<u8> {
+    props
+        .iter()
+        .flatten()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+// read indices stored as u32s, or as u16 pairs with each row padded to a u32 boundary
+fn decode_indices(bytes: &[u8], size: UVec2, use_u16: bool) -> Vec<u32> {
+    let mut indices = Vec::with_capacity((size.x * size.y) as usize);
+    for y in 0..size.y as usize {
+        for x in 0..size.x as usize {
+            let index = if use_u16 {
+                let offset = (y * size.x.div_ceil(2) as usize * 2 + x) * 2;
+                u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap()) as u32
+            } else {
+                let offset = (y * size.x as usize + x) * 4;
+                u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+            };
+            indices.push(index);
+        }
+    }
+    indices
+}
+
+// returns the bytes and the r32uint texel width
+fn encode_indices(indices: &[u32], size: UVec2, use_u16: bool) -> (Vec<u8>, u32) {
+    if !use_u16 {
+        let bytes = indices.iter().flat_map(|ix| ix.to_le_bytes()).collect();
+        return (bytes, size.x);
+    }
+
+    let width = size.x.div_ceil(2);
+    let mut bytes = Vec::with_capacity((width * size.y * 4) as usize);
+    for row in indices.chunks_exact(size.x as usize) {
+        bytes.extend(row.iter().flat_map(|ix| (*ix as u16).to_le_bytes()));
+        if size.x & 1 == 1 {
+            bytes.extend([0, 0]);
+        }
+    }
+    (bytes, width)
+}
+
+// append a mip level to the image, copying rows of `width` texels into the level's size.
+// returns false if the data doesn't fit
+fn push_mip_level(image: &mut Image, data: Vec<u8>, width: u32, texel_bytes: usize) -> bool {
+    let level = image.texture_descriptor.mip_level_count;
+    let base = image.texture_descriptor.size;
+    let level_width = (base.width >> level).max(1);
+    let level_height = (base.height >> level).max(1);
+    let height = (data.len() / (width as usize * texel_bytes)) as u32;
+    if width > level_width || height > level_height {
+        return false;
+    }
+
+    let row_bytes = width as usize * texel_bytes;
+    let level_row_bytes = level_width as usize * texel_bytes;
+    for row in 0..level_height as usize {
+        let start = image.data.len();
+        if row < height as usize {
+            image
+                .data
+                .extend_from_slice(&data[row * row_bytes..(row + 1) * row_bytes]);
+        }
+        image.data.resize(start + level_row_bytes, 0);
+    }
+    image.texture_descriptor.mip_level_count += 1;
+    true
+}
+
 pub fn pack_asset(grid_size: usize, image: &Image) -> (Image, UVec2, UVec2) {
     let width = image.width() as usize;
     let pixels_per_tile = width / grid_size;
-    let mut used_x = std::iter::repeat(false)
-        .take(pixels_per_tile)
-        .collect::<Vec<_>>();
-    let mut used_y = std::iter::repeat(false)
-        .take(pixels_per_tile)
-        .collect::<Vec<_>>();
+    let mut used_x = std::iter::repeat_n(false, pixels_per_tile).collect::<Vec<_>>();
+    let mut used_y = std::iter::repeat_n(false, pixels_per_tile).collect::<Vec<_>>();
 
     let data: &[u32] = bytemuck::cast_slice(&image.data);
 
@@ -307,8 +554,10 @@ pub fn pack_asset(grid_size: usize, image: &Image) -> (Image, UVec2, UVec2) {
         std::process::exit(1);
     }
 
-    let mut new_data =
-        Vec::from_iter(std::iter::repeat(0u32).take(x_count * y_count * 2 * grid_size * grid_size));
+    let mut new_data = Vec::from_iter(std::iter::repeat_n(
+        0u32,
+        x_count * y_count * 2 * grid_size * grid_size,
+    ));
     for grid_y in 0..grid_size {
         for grid_x in 0..grid_size {
             for pix_y in 0..y_count {
@@ -350,6 +599,56 @@ pub fn pack_asset(grid_size: usize, image: &Image) -> (Image, UVec2, UVec2) {
     )
 }
 
+// unique pixels of a flat (rg32uint) image, and an index into them per pixel
+pub(crate) struct IndexedPixels {
+    pub palette: Vec<[u8; 8]>,
+    pub indices: Vec<u32>,
+    // size of the (square-ish, padded) palette image
+    pub size: UVec2,
+    // whether the indices fit in 16 bits
+    pub use_u16: bool,
+}
+
+// indexes the pixels, if storing the palette and indices is smaller than the flat image.
+// shared by `write_asset` and `Imposter::from_bake`
+pub(crate) fn index_pixels(data: &[u8]) -> Option<IndexedPixels> {
+    // gather unique pixel pairs
+    let mut lookup = BTreeMap::<[u8; 8], u32>::default();
+    for chunk in data.chunks_exact(8) {
+        lookup.entry(chunk.try_into().unwrap()).or_default();
+    }
+
+    let pixels_x = (lookup.len() as f32).sqrt().ceil() as u32;
+    let pixels_y = (lookup.len() as f32 / pixels_x as f32).ceil() as u32;
+
+    let unique_pixel_count = pixels_x * pixels_y;
+    let use_u16 = unique_pixel_count < 65536;
+
+    let base_pixel_count = (data.len() / 8) as u32;
+    let total_index_size_bytes =
+        unique_pixel_count * 8 + base_pixel_count * if use_u16 { 2 } else { 4 };
+    if total_index_size_bytes >= base_pixel_count * 8 {
+        return None;
+    }
+
+    let mut palette = Vec::with_capacity(lookup.len());
+    for (pixel, index) in lookup.iter_mut() {
+        *index = palette.len() as u32;
+        palette.push(*pixel);
+    }
+    let indices = data
+        .chunks_exact(8)
+        .map(|chunk| lookup[<&[u8; 8]>::try_from(chunk).unwrap()])
+        .collect();
+
+    Some(IndexedPixels {
+        palette,
+        indices,
+        size: UVec2::new(pixels_x, pixels_y),
+        use_u16,
+    })
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn write_asset(
     path: &PathBuf,
@@ -376,32 +675,25 @@ pub fn write_asset(
 
     let mut wrote_indexed = false;
     if index {
-        // gather unique pixel pairs
-        let mut pixels = BTreeSet::<[u8; 8]>::default();
-        for chunk in image.data.chunks_exact(8) {
-            pixels.insert(chunk.try_into().unwrap());
-        }
-
-        let pixels_x = (pixels.len() as f32).sqrt().ceil() as u32;
-        let pixels_y = (pixels.len() as f32 / pixels_x as f32).ceil() as u32;
-
-        let unique_pixel_count = pixels_x * pixels_y;
-        let use_u16 = unique_pixel_count < 65536;
-
-        let base_pixel_count = image.width() * image.height();
-        let total_index_size_bytes =
-            unique_pixel_count * 8 + base_pixel_count * if use_u16 { 2 } else { 4 };
-        let base_size = base_pixel_count * 8;
-        if total_index_size_bytes < base_size {
+        if let Some(IndexedPixels {
+            palette,
+            indices,
+            size: UVec2 {
+                x: pixels_x,
+                y: pixels_y,
+            },
+            use_u16,
+        }) = index_pixels(&image.data)
+        {
             wrote_indexed = true;
 
             // write unique pixels to an image
-            let mut pixel_data = pixels.iter().copied().flatten().collect::<Vec<_>>();
+            let mut pixel_data = palette.iter().copied().flatten().collect::<Vec<_>>();
             // pad to square
-            pixel_data.extend(
-                std::iter::repeat(0u8)
-                    .take(((pixels_x * pixels_y * 8) as usize).saturating_sub(pixel_data.len())),
-            );
+            pixel_data.extend(std::iter::repeat_n(
+                0u8,
+                ((pixels_x * pixels_y * 8) as usize).saturating_sub(pixel_data.len()),
+            ));
             let pixels_image = Image::new(
                 Extent3d {
                     width: pixels_x,
@@ -438,45 +730,8 @@ pub fn write_asset(
                 pixels_x * pixels_y,
                 use_u16
             );
-            let pixel_lookup = pixels
-                .into_iter()
-                .enumerate()
-                .map(|(ix, p)| (p, ix))
-                .collect::<BTreeMap<_, _>>();
-            let mut pixel_indices = image
-                .data
-                .chunks_exact(8)
-                .flat_map(|chunk| {
-                    let chunk: [u8; 8] = chunk.try_into().unwrap();
-                    let index = *pixel_lookup.get(&chunk).unwrap();
-                    if use_u16 {
-                        (index as u16).to_le_bytes().to_vec()
-                    } else {
-                        (index as u32).to_le_bytes().to_vec()
-                    }
-                })
-                .collect::<Vec<_>>();
-
-            let width = if use_u16 {
-                if image.width() & 1 == 1 {
-                    // pad each line to u32 boundary
-                    for i in 0..image.height() {
-                        pixel_indices.insert(
-                            (image.width() * 2 + i * (image.width() * 2 + 2)) as usize,
-                            0,
-                        );
-                        pixel_indices.insert(
-                            (image.width() * 2 + i * (image.width() * 2 + 2)) as usize,
-                            0,
-                        );
-                    }
-                    image.width() / 2 + 1
-                } else {
-                    image.width() / 2
-                }
-            } else {
-                image.width()
-            };
+            let (pixel_indices, width) =
+                encode_indices(&indices, UVec2::new(image.width(), image.height()), use_u16);
             let indices_image = Image::new(
                 Extent3d {
                     width,
@@ -520,7 +775,78 @@ pub fn write_asset(
         zip.write_all(&cursor.into_inner())?;
     }
 
-    // write settings
+    write_settings(
+        &mut zip,
+        options,
+        scale,
+        grid_size,
+        tile_size,
+        mode,
+        packed_offset,
+        packed_size,
+    )?;
+    zip.finish()?;
+    info!("saved imposter to `{}`", path.to_string_lossy());
+    Ok(())
+}
+
+/// write an asset using the filterable storage, with separate albedo, normal and props textures that can be
+/// hardware filtered. if `compress` is set they are block compressed (bc7 / bc5), which requires gpu support to load.
+#[allow(clippy::too_many_arguments)]
+pub fn write_filterable_asset(
+    path: &PathBuf,
+    scale: f32,
+    grid_size: u32,
+    tile_size: u32,
+    mode: GridMode,
+    image: Image,
+    pack: bool,
+    compress: bool,
+) -> Result<(), anyhow::Error> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let file = std::fs::File::create(path)?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    let (image, packed_offset, packed_size) = if pack {
+        pack_asset(grid_size as usize, &image)
+    } else {
+        (image, UVec2::ZERO, UVec2::splat(tile_size))
+    };
+
+    let size = UVec2::new(image.width(), image.height());
+    for (name, data) in filterable_ktx2_files(size, &props_from_bytes(&image.data), compress) {
+        zip.start_file(name, options)?;
+        zip.write_all(&data)?;
+    }
+
+    write_settings(
+        &mut zip,
+        options,
+        scale,
+        grid_size,
+        tile_size,
+        mode,
+        packed_offset,
+        packed_size,
+    )?;
+    zip.finish()?;
+    info!("saved imposter to `{}`", path.to_string_lossy());
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_settings(
+    zip: &mut zip::ZipWriter<std::fs::File>,
+    options: zip::write::SimpleFileOptions,
+    scale: f32,
+    grid_size: u32,
+    tile_size: u32,
+    mode: GridMode,
+    packed_offset: UVec2,
+    packed_size: UVec2,
+) -> Result<(), anyhow::Error> {
     zip.start_file("settings.txt", options)?;
     let mode = match mode {
         GridMode::Spherical => "spherical",
@@ -534,7 +860,73 @@ pub fn write_asset(
         )
         .as_bytes(),
     )?;
-    zip.finish()?;
-    info!("saved imposter to `{}`", path.to_string_lossy());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indices(size: UVec2) -> Vec<u32> {
+        (0..size.x * size.y).map(|i| (i * 7919) % 60000).collect()
+    }
+
+    #[test]
+    fn indices_round_trip_u32() {
+        let size = UVec2::new(3, 4);
+        let indices = indices(size);
+        let (bytes, width) = encode_indices(&indices, size, false);
+        assert_eq!(width, 3);
+        assert_eq!(bytes.len(), 3 * 4 * 4);
+        assert_eq!(decode_indices(&bytes, size, false), indices);
+    }
+
+    #[test]
+    fn indices_round_trip_u16() {
+        let size = UVec2::new(4, 3);
+        let indices = indices(size);
+        let (bytes, width) = encode_indices(&indices, size, true);
+        assert_eq!(width, 2);
+        assert_eq!(bytes.len(), 2 * 3 * 4);
+        assert_eq!(decode_indices(&bytes, size, true), indices);
+    }
+
+    #[test]
+    fn indices_round_trip_u16_odd_width() {
+        let size = UVec2::new(5, 3);
+        let indices = indices(size);
+        let (bytes, width) = encode_indices(&indices, size, true);
+        // each row is padded to a whole r32uint texel
+        assert_eq!(width, 3);
+        assert_eq!(bytes.len(), 3 * 3 * 4);
+        for row in bytes.chunks_exact(12) {
+            assert_eq!(row[10..], [0, 0]);
+        }
+        assert_eq!(decode_indices(&bytes, size, true), indices);
+    }
+
+    #[test]
+    fn index_pixels_round_trip() {
+        let pixels = [[1u8; 8], [2; 8], [1; 8], [3; 8]];
+        let data = pixels.repeat(16).concat();
+        let indexed = index_pixels(&data).unwrap();
+        assert_eq!(indexed.palette.len(), 3);
+        assert_eq!(indexed.size, UVec2::new(2, 2));
+        assert!(indexed.use_u16);
+        for (pixel, index) in data.chunks_exact(8).zip(indexed.indices) {
+            assert_eq!(pixel, indexed.palette[index as usize]);
+        }
+    }
+
+    #[test]
+    fn index_pixels_skips_unique_pixels() {
+        let data = (0..64u64).flat_map(u64::to_le_bytes).collect::<Vec<_>>();
+        assert!(index_pixels(&data).is_none());
+    }
+
+    #[test]
+    fn props_bytes_round_trip() {
+        let props = vec![[1, 2], [u32::MAX, 0], [0x1234_5678, 0x9abc_def0]];
+        assert_eq!(props_from_bytes(&props_to_bytes(&props)), props);
+    }
+}
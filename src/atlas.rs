@@ -0,0 +1,1269 @@
+use std::ops::Range;
+
+use bevy::{
+    core_pipeline::{
+        core_3d::{AlphaMask3d, Camera3d, Opaque3d, Opaque3dBinKey, Transparent3d},
+        deferred::{AlphaMask3dDeferred, Opaque3dDeferred},
+        oit::OrderIndependentTransparencySettings,
+        prepass::{
+            AlphaMask3dPrepass, DeferredPrepass, DepthPrepass, MotionVectorPrepass, NormalPrepass,
+            Opaque3dPrepass, OpaqueNoLightmap3dBinKey,
+        },
+        tonemapping::{DebandDither, Tonemapping},
+    },
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    pbr::{
+        alpha_mode_pipeline_key, irradiance_volume::IrradianceVolume,
+        screen_space_specular_transmission_pipeline_key, tonemapping_pipeline_key, LightEntity,
+        MaterialPipelineKey, MeshPipeline, MeshPipelineKey, PrepassPipeline, RenderViewLightProbes,
+        ScreenSpaceAmbientOcclusion, SetMeshViewBindGroup, SetPrepassViewBindGroup, Shadow,
+        ShadowBinKey, ShadowFilteringMethod,
+    },
+    prelude::*,
+    render::{
+        camera::TemporalJitter,
+        mesh::{MeshVertexBufferLayoutRef, MeshVertexBufferLayouts, PrimitiveTopology},
+        render_asset::RenderAssets,
+        render_phase::{
+            AddRenderCommand, BinnedPhaseItem, BinnedRenderPhase, BinnedRenderPhaseType,
+            DrawFunctionId, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
+            RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewBinnedRenderPhases,
+            ViewSortedRenderPhases,
+        },
+        render_resource::{
+            binding_types::{storage_buffer_read_only, texture_2d_array},
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BindingResource,
+            CachedRenderPipelineId, CommandEncoderDescriptor, Extent3d, ImageCopyTexture, Origin3d,
+            PipelineCache, RenderPipelineDescriptor, ShaderDefVal, SpecializedMeshPipeline,
+            SpecializedMeshPipelineError, SpecializedMeshPipelines, StorageBuffer, Texture,
+            TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType,
+            TextureUsages, TextureView, TextureViewDescriptor, TextureViewDimension,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        storage::GpuShaderStorageBuffer,
+        sync_world::{MainEntity, TemporaryRenderEntity},
+        texture::GpuImage,
+        view::ExtractedView,
+        Extract, Render, RenderApp, RenderSet,
+    },
+    utils::HashSet,
+};
+use wgpu::ShaderStages;
+
+use crate::{
+    debug::ImposterDebugMode,
+    indirect::{GpuImposterIndirect, DRAW_INDIRECT_ARGS_SIZE},
+    oct_coords::GRID_MASK,
+    render::{
+        specialize_imposter, ImposterKey, FILTERABLE_FLAG, FRAGMENT_HANDLE,
+        IMPOSTER_WIND_BUFFER_HANDLE, INDEXED_FLAG, SHADOW_VIEW_KEY, VERTEX_HANDLE,
+        VERTEX_TILES_FLAG, WIND_FLAG,
+    },
+    Imposter, ImposterData,
+};
+
+/// draws imposters from a shared set of texture arrays, so that any number of instances of any of the
+/// atlas entries can be drawn with a single instanced draw call per grid mode.
+/// requires the [`ImposterRenderPlugin`](crate::ImposterRenderPlugin).
+pub struct ImposterAtlasPlugin;
+
+impl Plugin for ImposterAtlasPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ImposterAtlas>();
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ExtractedImposterAtlas>()
+            .init_resource::<ImposterAtlasInstances>()
+            .init_resource::<GpuImposterAtlas>()
+            .add_render_command::<Opaque3d, DrawImposterAtlas>()
+            .add_render_command::<AlphaMask3d, DrawImposterAtlas>()
+            .add_render_command::<Transparent3d, DrawImposterAtlas>()
+            .add_render_command::<Transparent3d, DrawImposterAtlasSorted>()
+            .add_render_command::<Opaque3dPrepass, DrawImposterAtlasPrepass>()
+            .add_render_command::<AlphaMask3dPrepass, DrawImposterAtlasPrepass>()
+            .add_render_command::<Opaque3dDeferred, DrawImposterAtlasPrepass>()
+            .add_render_command::<AlphaMask3dDeferred, DrawImposterAtlasPrepass>()
+            .add_render_command::<Shadow, DrawImposterAtlasPrepass>()
+            .add_systems(ExtractSchedule, extract_imposter_atlas)
+            .add_systems(
+                Render,
+                (
+                    (
+                        queue_imposter_atlas,
+                        queue_imposter_atlas_prepass,
+                        queue_imposter_atlas_shadows,
+                    )
+                        .in_set(RenderSet::QueueMeshes),
+                    prepare_imposter_atlas.in_set(RenderSet::PrepareResources),
+                    prepare_imposter_atlas_bind_group.in_set(RenderSet::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ImposterAtlasPipeline>()
+            .init_resource::<SpecializedMeshPipelines<ImposterAtlasPipeline>>();
+    }
+}
+
+/// the set of imposters that can be drawn via [`ImposterAtlasInstance`]s.
+/// the imposter textures are copied into shared texture arrays on the gpu, so the images must be
+/// created with `TextureUsages::COPY_SRC` (imposters loaded from file and baked targets are).
+/// the number of entries is limited by the device's `max_texture_array_layers` (at least 256), the atlas is not
+/// built if there are more. entries must not use the filterable storage (see
+/// `save_filterable_asset_callback`), the atlas is not built if any do. the atlas is rebuilt when an entry's `Imposter` asset is modified.
+/// removing an entry's asset frees its slot, instances of it are no longer drawn.
+#[derive(Resource)]
+pub struct ImposterAtlas {
+    imposters: Vec<Handle<Imposter>>,
+    // used for all atlas instances
+    pub alpha_mode: AlphaMode,
+}
+
+impl Default for ImposterAtlas {
+    fn default() -> Self {
+        Self {
+            imposters: Vec::default(),
+            alpha_mode: AlphaMode::Mask(0.5),
+        }
+    }
+}
+
+impl ImposterAtlas {
+    /// add an imposter to the atlas, returning the index to use in [`ImposterAtlasInstance`]s.
+    /// adding the same handle again returns the existing index.
+    pub fn add(&mut self, imposter: Handle<Imposter>) -> u32 {
+        if let Some(index) = self.imposters.iter().position(|h| h == &imposter) {
+            return index as u32;
+        }
+
+        self.imposters.push(imposter);
+        self.imposters.len() as u32 - 1
+    }
+
+    pub fn imposters(&self) -> &[Handle<Imposter>] {
+        &self.imposters
+    }
+}
+
+/// draws an instance of the [`ImposterAtlas`] entry with the given index
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+#[require(Transform, Visibility)]
+pub struct ImposterAtlasInstance(pub u32);
+
+// see `render::uniform`
+#[allow(dead_code)]
+mod uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    use crate::ImposterData;
+
+    #[derive(ShaderType, Clone, Copy)]
+    pub struct ImposterAtlasEntry {
+        pub data: ImposterData,
+        pub pixels_size: UVec2,
+    }
+
+    #[derive(ShaderType, Clone, Copy)]
+    pub struct ImposterAtlasInstanceData {
+        pub world_from_local: Mat4,
+        pub index: u32,
+    }
+}
+pub use uniform::{ImposterAtlasEntry, ImposterAtlasInstanceData};
+
+// data, pixels, indices
+pub(crate) type ExtractedAtlasEntry = (ImposterData, AssetId<Image>, AssetId<Image>);
+
+#[derive(Resource, Default)]
+pub struct ExtractedImposterAtlas {
+    // `None` once the entry's imposter is removed
+    pub(crate) entries: Vec<Option<ExtractedAtlasEntry>>,
+    // removed entries, which leave an empty slot rather than being waited for
+    removed: HashSet<AssetId<Imposter>>,
+    alpha_mode: AlphaMode,
+    // waiting for the imposter assets to load
+    pending: bool,
+    // waiting for the gpu images to be prepared
+    dirty: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct ImposterAtlasInstances {
+    // sorted by grid mode
+    instances: Vec<ImposterAtlasInstanceData>,
+    buffer: StorageBuffer<Vec<ImposterAtlasInstanceData>>,
+}
+
+// a render entity for each grid mode with visible instances
+#[derive(Component)]
+pub struct ImposterAtlasBatch {
+    pub(crate) flags: u32,
+    pub(crate) range: Range<u32>,
+    // instances of the `ImposterIndirectPlugin`
+    pub(crate) indirect: bool,
+    // drawn from the per-view culled instances of the `ImposterIndirectPlugin`. shadow views aren't culled, so
+    // they draw the `range` of an unculled batch instead
+    pub(crate) culled: bool,
+}
+
+#[allow(clippy::type_complexity)]
+pub fn extract_imposter_atlas(
+    mut commands: Commands,
+    mut extracted: ResMut<ExtractedImposterAtlas>,
+    mut instances: ResMut<ImposterAtlasInstances>,
+    atlas: Extract<Res<ImposterAtlas>>,
+    imposters: Extract<Res<Assets<Imposter>>>,
+    mut imposter_events: Extract<EventReader<AssetEvent<Imposter>>>,
+    query: Extract<
+        Query<(
+            &ImposterAtlasInstance,
+            &GlobalTransform,
+            &InheritedVisibility,
+        )>,
+    >,
+) {
+    let in_atlas = |id: &AssetId<Imposter>| atlas.imposters.iter().any(|handle| handle.id() == *id);
+
+    if atlas.is_changed() {
+        extracted.pending = true;
+        extracted.alpha_mode = atlas.alpha_mode;
+        extracted.removed.retain(in_atlas);
+    }
+
+    // recopy when an entry's data or images change, or when it is removed
+    for event in imposter_events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } if in_atlas(id) => {
+                extracted.removed.remove(id);
+                extracted.pending = true;
+            }
+            AssetEvent::Removed { id } if in_atlas(id) => {
+                extracted.removed.insert(*id);
+                extracted.pending = true;
+            }
+            _ => (),
+        }
+    }
+
+    if extracted.pending {
+        let entries = atlas
+            .imposters
+            .iter()
+            .map(|handle| match imposters.get(handle) {
+                Some(imposter) => Some(Some((
+                    imposter.data,
+                    imposter.pixels.id(),
+                    imposter.indices.id(),
+                ))),
+                // keep the slot so that the other indices stay valid
+                None if extracted.removed.contains(&handle.id()) => Some(None),
+                None => None,
+            })
+            .collect::<Option<Vec<_>>>();
+
+        if let Some(entries) = entries {
+            extracted.entries = entries;
+            extracted.pending = false;
+            extracted.dirty = true;
+        }
+    }
+
+    let mut visible = query
+        .iter()
+        .filter(|(_, _, visibility)| visibility.get())
+        .filter_map(|(instance, transform, _)| {
+            let (data, ..) = extracted.entries.get(instance.0 as usize)?.as_ref()?;
+            Some((
+                data.flags & GRID_MASK,
+                ImposterAtlasInstanceData {
+                    world_from_local: transform.compute_matrix(),
+                    index: instance.0,
+                },
+            ))
+        })
+        .collect::<Vec<_>>();
+    visible.sort_by_key(|(flags, _)| *flags);
+
+    let mut start = 0;
+    while start < visible.len() {
+        let flags = visible[start].0;
+        let end = start + visible[start..].partition_point(|(f, _)| *f == flags);
+        commands.spawn((
+            ImposterAtlasBatch {
+                flags,
+                range: start as u32..end as u32,
+                indirect: false,
+                culled: false,
+            },
+            TemporaryRenderEntity,
+        ));
+        start = end;
+    }
+
+    instances.instances = visible.into_iter().map(|(_, data)| data).collect();
+}
+
+#[derive(Resource, Default)]
+pub struct GpuImposterAtlas {
+    // pixels, indices
+    pub(crate) textures: Option<(Texture, TextureView, Texture, TextureView)>,
+    pub(crate) entries: StorageBuffer<Vec<ImposterAtlasEntry>>,
+    bind_group: Option<BindGroup>,
+    empty_bind_group: Option<BindGroup>,
+}
+
+impl GpuImposterAtlas {
+    // the group 2 bind group drawing `instances`, shared by direct and indirect draws so the entries can't drift
+    // from the layout. `None` until the atlas textures and the wind buffer are ready
+    pub(crate) fn create_bind_group(
+        &self,
+        label: &'static str,
+        render_device: &RenderDevice,
+        pipeline: &ImposterAtlasPipeline,
+        instances: BindingResource,
+        buffers: &RenderAssets<GpuShaderStorageBuffer>,
+    ) -> Option<BindGroup> {
+        let (Some((_, pixels, _, indices)), Some(entries), Some(wind)) = (
+            self.textures.as_ref(),
+            self.entries.binding(),
+            buffers.get(&IMPOSTER_WIND_BUFFER_HANDLE),
+        ) else {
+            return None;
+        };
+
+        Some(render_device.create_bind_group(
+            label,
+            &pipeline.layout,
+            &BindGroupEntries::sequential((
+                pixels,
+                indices,
+                entries,
+                instances,
+                wind.buffer.as_entire_binding(),
+            )),
+        ))
+    }
+}
+
+pub fn prepare_imposter_atlas(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    images: Res<RenderAssets<GpuImage>>,
+    mut extracted: ResMut<ExtractedImposterAtlas>,
+    mut gpu_atlas: ResMut<GpuImposterAtlas>,
+    mut instances: ResMut<ImposterAtlasInstances>,
+) {
+    let instances = &mut *instances;
+    if instances.instances.is_empty() {
+        // an empty write keeps the old contents, drop the buffer so stale instances can't be bound
+        instances.buffer = StorageBuffer::default();
+    } else {
+        instances
+            .buffer
+            .set(std::mem::take(&mut instances.instances));
+        instances.buffer.write_buffer(&render_device, &render_queue);
+    }
+
+    if !extracted.dirty || extracted.entries.is_empty() {
+        return;
+    }
+
+    // filterable storage has no packed pixels to copy
+    let filterable = extracted
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| {
+            entry
+                .as_ref()
+                .is_some_and(|(data, ..)| data.flags & FILTERABLE_FLAG != 0)
+        })
+        .map(|(ix, _)| ix)
+        .collect::<Vec<_>>();
+    if !filterable.is_empty() {
+        error!(
+            "`ImposterAtlas` entries {filterable:?} use filterable storage, which the atlas doesn't support. the atlas will not be drawn"
+        );
+        extracted.dirty = false;
+        gpu_atlas.textures = None;
+        return;
+    }
+
+    let Some(sources) = extracted
+        .entries
+        .iter()
+        .map(|entry| {
+            let Some((data, pixels, indices)) = entry else {
+                return Some(None);
+            };
+            let indices = if data.flags & INDEXED_FLAG != 0 {
+                Some(images.get(*indices)?)
+            } else {
+                None
+            };
+            Some(Some((images.get(*pixels)?, indices)))
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    extracted.dirty = false;
+
+    let layers = sources.len() as u32;
+    let max_layers = render_device.limits().max_texture_array_layers;
+    if layers > max_layers {
+        error!(
+            "`ImposterAtlas` has {layers} entries, more than the device's `max_texture_array_layers` ({max_layers}). the atlas will not be drawn"
+        );
+        gpu_atlas.textures = None;
+        return;
+    }
+
+    // size the layers to fit the largest entry
+    let pixels_size = sources
+        .iter()
+        .flatten()
+        .fold(UVec2::ONE, |size, (pixels, _)| size.max(pixels.size));
+    let indices_size = sources
+        .iter()
+        .flatten()
+        .filter_map(|(_, indices)| *indices)
+        .fold(UVec2::ONE, |size, indices| size.max(indices.size));
+
+    let create_texture = |label, size: UVec2, format| {
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..default()
+        });
+        (texture, view)
+    };
+
+    let (pixels_texture, pixels_view) = create_texture(
+        "imposter_atlas_pixels",
+        pixels_size,
+        TextureFormat::Rg32Uint,
+    );
+    let (indices_texture, indices_view) = create_texture(
+        "imposter_atlas_indices",
+        indices_size,
+        TextureFormat::R32Uint,
+    );
+
+    let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("imposter_atlas_copy"),
+    });
+    let mut copy = |source: &GpuImage, target: &Texture, layer: u32| {
+        command_encoder.copy_texture_to_texture(
+            source.texture.as_image_copy(),
+            ImageCopyTexture {
+                texture: target,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: source.size.x,
+                height: source.size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+    };
+
+    // freed slots leave their layer empty
+    for (layer, (pixels, indices)) in sources
+        .iter()
+        .enumerate()
+        .filter_map(|(layer, source)| Some((layer, source.as_ref()?)))
+    {
+        copy(pixels, &pixels_texture, layer as u32);
+        if let Some(indices) = indices {
+            copy(indices, &indices_texture, layer as u32);
+        }
+    }
+    render_queue.submit([command_encoder.finish()]);
+
+    gpu_atlas.entries.set(
+        extracted
+            .entries
+            .iter()
+            .zip(sources.iter())
+            .map(|(entry, source)| match (entry, source) {
+                (Some((data, ..)), Some((pixels, _))) => ImposterAtlasEntry {
+                    data: *data,
+                    pixels_size: pixels.size,
+                },
+                // no pixels, so the cull pass skips instances of freed slots
+                _ => ImposterAtlasEntry {
+                    data: ImposterData::default(),
+                    pixels_size: UVec2::ZERO,
+                },
+            })
+            .collect(),
+    );
+    gpu_atlas
+        .entries
+        .write_buffer(&render_device, &render_queue);
+    gpu_atlas.textures = Some((pixels_texture, pixels_view, indices_texture, indices_view));
+}
+
+pub fn prepare_imposter_atlas_bind_group(
+    render_device: Res<RenderDevice>,
+    pipeline: Res<ImposterAtlasPipeline>,
+    mut gpu_atlas: ResMut<GpuImposterAtlas>,
+    instances: Res<ImposterAtlasInstances>,
+    buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+) {
+    let gpu_atlas = &mut *gpu_atlas;
+    gpu_atlas.bind_group = instances.buffer.binding().and_then(|instances| {
+        gpu_atlas.create_bind_group(
+            "imposter_atlas_bind_group",
+            &render_device,
+            &pipeline,
+            instances,
+            &buffers,
+        )
+    });
+    if gpu_atlas.bind_group.is_none() {
+        return;
+    }
+
+    if gpu_atlas.empty_bind_group.is_none() {
+        gpu_atlas.empty_bind_group = Some(render_device.create_bind_group(
+            "imposter_atlas_empty_bind_group",
+            &pipeline.empty_layout,
+            &[],
+        ));
+    }
+}
+
+#[derive(Resource)]
+pub struct ImposterAtlasPipeline {
+    mesh_pipeline: MeshPipeline,
+    // the imposter material's prepass pipeline, with the atlas layouts swapped in
+    prepass_pipeline: PrepassPipeline<Imposter>,
+    // group 1 is unused for direct draws
+    empty_layout: BindGroupLayout,
+    // group 1 holds the culled instance indices for indirect draws
+    pub visible_layout: BindGroupLayout,
+    pub layout: BindGroupLayout,
+    vertex_layout: MeshVertexBufferLayoutRef,
+}
+
+impl FromWorld for ImposterAtlasPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(
+            "imposter_atlas_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    texture_2d_array(TextureSampleType::Uint),
+                    texture_2d_array(TextureSampleType::Uint),
+                    storage_buffer_read_only::<ImposterAtlasEntry>(false),
+                    storage_buffer_read_only::<ImposterAtlasInstanceData>(false),
+                    storage_buffer_read_only::<Vec4>(false),
+                ),
+            ),
+        );
+        let empty_layout =
+            render_device.create_bind_group_layout("imposter_atlas_empty_layout", &[]);
+        let visible_layout = render_device.create_bind_group_layout(
+            "imposter_atlas_visible_layout",
+            &BindGroupLayoutEntries::single(
+                ShaderStages::VERTEX,
+                storage_buffer_read_only::<u32>(false),
+            ),
+        );
+
+        // the quad is generated in the vertex shader, but the mesh pipeline requires a layout with positions
+        let vertex_layout = Mesh::from(Rectangle::default())
+            .get_mesh_vertex_buffer_layout(&mut world.resource_mut::<MeshVertexBufferLayouts>());
+
+        Self {
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            prepass_pipeline: PrepassPipeline::from_world(world),
+            empty_layout,
+            visible_layout,
+            layout,
+            vertex_layout,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ImposterAtlasPipelineKey {
+    pub mesh_key: MeshPipelineKey,
+    // imposter grid flags, with `SHADOW_VIEW_KEY` for shadow views
+    pub flags: u32,
+    // drawn from the culled instances of the view
+    pub indirect: bool,
+    // drawn with the prepass pipeline, for the prepass, deferred and shadow phases
+    pub prepass: bool,
+    // the global debug mode, atlas entries have no per-material mode
+    pub debug_mode: ImposterDebugMode,
+}
+
+impl SpecializedMeshPipeline for ImposterAtlasPipeline {
+    type Key = ImposterAtlasPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        // atlas instances are far-field so select tiles per vertex, and entries without wind get no offset
+        let imposter_key = ImposterKey(key.flags | VERTEX_TILES_FLAG | WIND_FLAG, key.debug_mode);
+        let mut descriptor = if key.prepass {
+            let mut descriptor = self.prepass_pipeline.specialize(
+                MaterialPipelineKey {
+                    mesh_key: key.mesh_key,
+                    bind_group_data: imposter_key,
+                },
+                layout,
+            )?;
+            descriptor.label = Some("imposter_atlas_prepass_pipeline".into());
+            descriptor
+        } else {
+            let mut descriptor = self.mesh_pipeline.specialize(key.mesh_key, layout)?;
+            specialize_imposter(&mut descriptor, key.mesh_key, imposter_key, FRAGMENT_HANDLE);
+            descriptor.label = Some("imposter_atlas_pipeline".into());
+            descriptor
+        };
+        descriptor.layout.truncate(1);
+        descriptor.layout.extend([
+            if key.indirect {
+                self.visible_layout.clone()
+            } else {
+                self.empty_layout.clone()
+            },
+            self.layout.clone(),
+        ]);
+
+        descriptor.vertex.shader = VERTEX_HANDLE;
+        descriptor.vertex.buffers.clear();
+        descriptor.vertex.shader_defs.push("IMPOSTER_ATLAS".into());
+        if key.indirect {
+            descriptor
+                .vertex
+                .shader_defs
+                .push("IMPOSTER_ATLAS_INDIRECT".into());
+            descriptor.vertex.shader_defs.push(ShaderDefVal::UInt(
+                "IMPOSTER_ATLAS_GRID_MODE".into(),
+                key.flags & GRID_MASK,
+            ));
+        }
+
+        // a depth-only prepass may not have a fragment stage
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader = FRAGMENT_HANDLE;
+            fragment.shader_defs.push("IMPOSTER_ATLAS".into());
+        }
+
+        Ok(descriptor)
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn queue_imposter_atlas(
+    (opaque_draw_functions, alpha_mask_draw_functions, transparent_draw_functions): (
+        Res<DrawFunctions<Opaque3d>>,
+        Res<DrawFunctions<AlphaMask3d>>,
+        Res<DrawFunctions<Transparent3d>>,
+    ),
+    pipeline: Res<ImposterAtlasPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ImposterAtlasPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    extracted: Res<ExtractedImposterAtlas>,
+    instances: Res<ImposterAtlasInstances>,
+    debug_mode: Res<ImposterDebugMode>,
+    batches: Query<(Entity, &ImposterAtlasBatch)>,
+    mut opaque_phases: ResMut<ViewBinnedRenderPhases<Opaque3d>>,
+    mut alpha_mask_phases: ResMut<ViewBinnedRenderPhases<AlphaMask3d>>,
+    mut transparent_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &Msaa,
+        Option<&Tonemapping>,
+        Option<&DebandDither>,
+        Option<&ShadowFilteringMethod>,
+        Has<ScreenSpaceAmbientOcclusion>,
+        (
+            Has<NormalPrepass>,
+            Has<DepthPrepass>,
+            Has<MotionVectorPrepass>,
+            Has<DeferredPrepass>,
+        ),
+        Option<&Camera3d>,
+        Has<TemporalJitter>,
+        Option<&Projection>,
+        (
+            Has<RenderViewLightProbes<EnvironmentMapLight>>,
+            Has<RenderViewLightProbes<IrradianceVolume>>,
+        ),
+        Has<OrderIndependentTransparencySettings>,
+    )>,
+) {
+    if batches.is_empty() {
+        return;
+    }
+
+    let draw_opaque = opaque_draw_functions.read().id::<DrawImposterAtlas>();
+    let draw_alpha_mask = alpha_mask_draw_functions.read().id::<DrawImposterAtlas>();
+    let draw_transparent = transparent_draw_functions.read().id::<DrawImposterAtlas>();
+    let draw_sorted = transparent_draw_functions
+        .read()
+        .id::<DrawImposterAtlasSorted>();
+
+    for (
+        view_entity,
+        view,
+        msaa,
+        tonemapping,
+        dither,
+        shadow_filter_method,
+        ssao,
+        (normal_prepass, depth_prepass, motion_vector_prepass, deferred_prepass),
+        camera_3d,
+        temporal_jitter,
+        projection,
+        (has_environment_maps, has_irradiance_volumes),
+        has_oit,
+    ) in &views
+    {
+        let (Some(opaque_phase), Some(alpha_mask_phase), Some(transparent_phase)) = (
+            opaque_phases.get_mut(&view_entity),
+            alpha_mask_phases.get_mut(&view_entity),
+            transparent_phases.get_mut(&view_entity),
+        ) else {
+            continue;
+        };
+
+        // `queue_imposter_atlas_prepass` draws these into the gbuffer instead
+        if deferred_prepass && draws_in_prepass(extracted.alpha_mode) {
+            continue;
+        }
+
+        // matches the view key used for materials
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr)
+            | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList)
+            | alpha_mode_pipeline_key(extracted.alpha_mode, msaa);
+
+        for (enabled, key) in [
+            (normal_prepass, MeshPipelineKey::NORMAL_PREPASS),
+            (depth_prepass, MeshPipelineKey::DEPTH_PREPASS),
+            (
+                motion_vector_prepass,
+                MeshPipelineKey::MOTION_VECTOR_PREPASS,
+            ),
+            (deferred_prepass, MeshPipelineKey::DEFERRED_PREPASS),
+            (temporal_jitter, MeshPipelineKey::TEMPORAL_JITTER),
+            (has_environment_maps, MeshPipelineKey::ENVIRONMENT_MAP),
+            (has_irradiance_volumes, MeshPipelineKey::IRRADIANCE_VOLUME),
+            (has_oit, MeshPipelineKey::OIT_ENABLED),
+            (ssao, MeshPipelineKey::SCREEN_SPACE_AMBIENT_OCCLUSION),
+        ] {
+            if enabled {
+                view_key |= key;
+            }
+        }
+
+        if let Some(projection) = projection {
+            view_key |= match projection {
+                Projection::Perspective(_) => MeshPipelineKey::VIEW_PROJECTION_PERSPECTIVE,
+                Projection::Orthographic(_) => MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC,
+            };
+        }
+
+        view_key |= match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+            ShadowFilteringMethod::Hardware2x2 => {
+                MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2
+            }
+            ShadowFilteringMethod::Gaussian => MeshPipelineKey::SHADOW_FILTER_METHOD_GAUSSIAN,
+            ShadowFilteringMethod::Temporal => MeshPipelineKey::SHADOW_FILTER_METHOD_TEMPORAL,
+        };
+
+        if !view.hdr {
+            if let Some(tonemapping) = tonemapping {
+                view_key |= MeshPipelineKey::TONEMAP_IN_SHADER;
+                view_key |= tonemapping_pipeline_key(*tonemapping);
+            }
+            if let Some(DebandDither::Enabled) = dither {
+                view_key |= MeshPipelineKey::DEBAND_DITHER;
+            }
+        }
+
+        if let Some(camera_3d) = camera_3d {
+            view_key |= screen_space_specular_transmission_pipeline_key(
+                camera_3d.screen_space_specular_transmission_quality,
+            );
+        }
+
+        for (batch_entity, batch) in &batches {
+            // unculled batches are only for shadow views
+            if batch.indirect && !batch.culled {
+                continue;
+            }
+
+            let pipeline_id = match pipelines.specialize(
+                &pipeline_cache,
+                &pipeline,
+                ImposterAtlasPipelineKey {
+                    mesh_key: view_key,
+                    flags: batch.flags,
+                    indirect: batch.culled,
+                    prepass: false,
+                    debug_mode: *debug_mode,
+                },
+                &pipeline.vertex_layout,
+            ) {
+                Ok(id) => id,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            // batches have no main world entity
+            let entity = (batch_entity, MainEntity::from(Entity::PLACEHOLDER));
+
+            match extracted.alpha_mode {
+                AlphaMode::Opaque => {
+                    opaque_phase.add(
+                        Opaque3dBinKey {
+                            pipeline: pipeline_id,
+                            draw_function: draw_opaque,
+                            asset_id: AssetId::<Mesh>::invalid().untyped(),
+                            material_bind_group_id: None,
+                            lightmap_image: None,
+                        },
+                        entity,
+                        BinnedRenderPhaseType::NonMesh,
+                    );
+                }
+                AlphaMode::Mask(_) | AlphaMode::AlphaToCoverage => {
+                    alpha_mask_phase.add(
+                        OpaqueNoLightmap3dBinKey {
+                            pipeline: pipeline_id,
+                            draw_function: draw_alpha_mask,
+                            asset_id: AssetId::<Mesh>::invalid().untyped(),
+                            material_bind_group_id: None,
+                        },
+                        entity,
+                        BinnedRenderPhaseType::NonMesh,
+                    );
+                }
+                // gpu culled instances can't be sorted
+                _ if batch.indirect => {
+                    transparent_phase.add(Transparent3d {
+                        distance: 0.0,
+                        pipeline: pipeline_id,
+                        entity,
+                        draw_function: draw_transparent,
+                        batch_range: 0..1,
+                        extra_index: PhaseItemExtraIndex::NONE,
+                    });
+                }
+                // an item per instance, so they sort by view-space distance like meshes do
+                _ => {
+                    let rangefinder = view.rangefinder3d();
+                    for index in batch.range.clone() {
+                        let translation =
+                            instances.instances[index as usize].world_from_local.w_axis;
+                        transparent_phase.add(Transparent3d {
+                            distance: rangefinder.distance_translation(&translation.truncate()),
+                            pipeline: pipeline_id,
+                            entity,
+                            draw_function: draw_sorted,
+                            batch_range: index..index + 1,
+                            extra_index: PhaseItemExtraIndex::NONE,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+// blended instances are left out of prepasses, like blended materials
+fn draws_in_prepass(alpha_mode: AlphaMode) -> bool {
+    matches!(
+        alpha_mode,
+        AlphaMode::Opaque | AlphaMode::Mask(_) | AlphaMode::AlphaToCoverage
+    )
+}
+
+/// bevy's `queue_prepass_material_meshes` for atlas batches. on views with a `DeferredPrepass` opaque and
+/// masked instances are drawn into the gbuffer, as with `DefaultOpaqueRendererMethod::deferred`
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn queue_imposter_atlas_prepass(
+    (
+        opaque_draw_functions,
+        alpha_mask_draw_functions,
+        opaque_deferred_draw_functions,
+        alpha_mask_deferred_draw_functions,
+    ): (
+        Res<DrawFunctions<Opaque3dPrepass>>,
+        Res<DrawFunctions<AlphaMask3dPrepass>>,
+        Res<DrawFunctions<Opaque3dDeferred>>,
+        Res<DrawFunctions<AlphaMask3dDeferred>>,
+    ),
+    pipeline: Res<ImposterAtlasPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ImposterAtlasPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    extracted: Res<ExtractedImposterAtlas>,
+    debug_mode: Res<ImposterDebugMode>,
+    batches: Query<(Entity, &ImposterAtlasBatch)>,
+    mut opaque_phases: ResMut<ViewBinnedRenderPhases<Opaque3dPrepass>>,
+    mut alpha_mask_phases: ResMut<ViewBinnedRenderPhases<AlphaMask3dPrepass>>,
+    mut opaque_deferred_phases: ResMut<ViewBinnedRenderPhases<Opaque3dDeferred>>,
+    mut alpha_mask_deferred_phases: ResMut<ViewBinnedRenderPhases<AlphaMask3dDeferred>>,
+    views: Query<
+        (
+            Entity,
+            &Msaa,
+            Has<DepthPrepass>,
+            Has<NormalPrepass>,
+            Has<MotionVectorPrepass>,
+            Has<DeferredPrepass>,
+        ),
+        With<ExtractedView>,
+    >,
+) {
+    if batches.is_empty() || !draws_in_prepass(extracted.alpha_mode) {
+        return;
+    }
+
+    let draw_opaque = opaque_draw_functions
+        .read()
+        .id::<DrawImposterAtlasPrepass>();
+    let draw_alpha_mask = alpha_mask_draw_functions
+        .read()
+        .id::<DrawImposterAtlasPrepass>();
+    let draw_opaque_deferred = opaque_deferred_draw_functions
+        .read()
+        .id::<DrawImposterAtlasPrepass>();
+    let draw_alpha_mask_deferred = alpha_mask_deferred_draw_functions
+        .read()
+        .id::<DrawImposterAtlasPrepass>();
+
+    for (
+        view_entity,
+        msaa,
+        depth_prepass,
+        normal_prepass,
+        motion_vector_prepass,
+        deferred_prepass,
+    ) in &views
+    {
+        let mut view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList)
+            | alpha_mode_pipeline_key(extracted.alpha_mode, msaa);
+        for (enabled, key) in [
+            (depth_prepass, MeshPipelineKey::DEPTH_PREPASS),
+            (normal_prepass, MeshPipelineKey::NORMAL_PREPASS),
+            (
+                motion_vector_prepass,
+                MeshPipelineKey::MOTION_VECTOR_PREPASS,
+            ),
+            (deferred_prepass, MeshPipelineKey::DEFERRED_PREPASS),
+        ] {
+            if enabled {
+                view_key |= key;
+            }
+        }
+
+        let mut items = Vec::new();
+        for (batch_entity, batch) in &batches {
+            if batch.indirect && !batch.culled {
+                continue;
+            }
+
+            match pipelines.specialize(
+                &pipeline_cache,
+                &pipeline,
+                ImposterAtlasPipelineKey {
+                    mesh_key: view_key,
+                    flags: batch.flags,
+                    indirect: batch.culled,
+                    prepass: true,
+                    debug_mode: *debug_mode,
+                },
+                &pipeline.vertex_layout,
+            ) {
+                Ok(id) => items.push((id, batch_entity)),
+                Err(err) => error!("{}", err),
+            }
+        }
+
+        let opaque = matches!(extracted.alpha_mode, AlphaMode::Opaque);
+        match (deferred_prepass, opaque) {
+            (true, true) => add_prepass_items(
+                opaque_deferred_phases.get_mut(&view_entity),
+                draw_opaque_deferred,
+                &items,
+            ),
+            (true, false) => add_prepass_items(
+                alpha_mask_deferred_phases.get_mut(&view_entity),
+                draw_alpha_mask_deferred,
+                &items,
+            ),
+            (false, true) => {
+                add_prepass_items(opaque_phases.get_mut(&view_entity), draw_opaque, &items)
+            }
+            (false, false) => add_prepass_items(
+                alpha_mask_phases.get_mut(&view_entity),
+                draw_alpha_mask,
+                &items,
+            ),
+        }
+    }
+}
+
+fn add_prepass_items<P: BinnedPhaseItem<BinKey = OpaqueNoLightmap3dBinKey>>(
+    phase: Option<&mut BinnedRenderPhase<P>>,
+    draw_function: DrawFunctionId,
+    items: &[(CachedRenderPipelineId, Entity)],
+) {
+    let Some(phase) = phase else {
+        return;
+    };
+
+    for (pipeline, batch_entity) in items {
+        phase.add(
+            OpaqueNoLightmap3dBinKey {
+                pipeline: *pipeline,
+                draw_function,
+                asset_id: AssetId::<Mesh>::invalid().untyped(),
+                material_bind_group_id: None,
+            },
+            (*batch_entity, MainEntity::from(Entity::PLACEHOLDER)),
+            BinnedRenderPhaseType::NonMesh,
+        );
+    }
+}
+
+/// draws atlas batches into every shadow view. shadow views aren't gpu culled, so indirect instances draw their
+/// unculled batches
+#[allow(clippy::too_many_arguments)]
+pub fn queue_imposter_atlas_shadows(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    pipeline: Res<ImposterAtlasPipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ImposterAtlasPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    extracted: Res<ExtractedImposterAtlas>,
+    debug_mode: Res<ImposterDebugMode>,
+    batches: Query<(Entity, &ImposterAtlasBatch)>,
+    mut shadow_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    light_views: Query<(Entity, &LightEntity)>,
+) {
+    if batches.is_empty() {
+        return;
+    }
+
+    let draw_shadow = shadow_draw_functions
+        .read()
+        .id::<DrawImposterAtlasPrepass>();
+    for (view_entity, light_entity) in &light_views {
+        let Some(shadow_phase) = shadow_phases.get_mut(&view_entity) else {
+            continue;
+        };
+
+        // as in `queue_imposter_shadows`
+        let mut view_key = MeshPipelineKey::DEPTH_PREPASS
+            | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList);
+        view_key.set(
+            MeshPipelineKey::DEPTH_CLAMP_ORTHO,
+            matches!(light_entity, LightEntity::Directional { .. }),
+        );
+        if extracted.alpha_mode != AlphaMode::Opaque {
+            view_key |= MeshPipelineKey::MAY_DISCARD;
+        }
+
+        for (batch_entity, batch) in &batches {
+            if batch.culled {
+                continue;
+            }
+
+            let pipeline_id = match pipelines.specialize(
+                &pipeline_cache,
+                &pipeline,
+                ImposterAtlasPipelineKey {
+                    mesh_key: view_key,
+                    flags: batch.flags | SHADOW_VIEW_KEY,
+                    indirect: false,
+                    prepass: true,
+                    debug_mode: *debug_mode,
+                },
+                &pipeline.vertex_layout,
+            ) {
+                Ok(id) => id,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            shadow_phase.add(
+                ShadowBinKey {
+                    draw_function: draw_shadow,
+                    pipeline: pipeline_id,
+                    asset_id: AssetId::<Mesh>::invalid().untyped(),
+                },
+                (batch_entity, MainEntity::from(Entity::PLACEHOLDER)),
+                BinnedRenderPhaseType::NonMesh,
+            );
+        }
+    }
+}
+
+pub type DrawImposterAtlas = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetImposterAtlasBindGroup,
+    DrawImposterAtlasBatch,
+);
+
+// draws the instance in the item's batch range, for sorted phases
+pub type DrawImposterAtlasSorted = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetImposterAtlasBindGroup,
+    DrawImposterAtlasItem,
+);
+
+pub type DrawImposterAtlasPrepass = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    SetImposterAtlasBindGroup,
+    DrawImposterAtlasBatch,
+);
+
+pub struct SetImposterAtlasBindGroup;
+impl<P: PhaseItem> RenderCommand<P> for SetImposterAtlasBindGroup {
+    type Param = (SRes<GpuImposterAtlas>, Option<SRes<GpuImposterIndirect>>);
+    type ViewQuery = Entity;
+    type ItemQuery = &'static ImposterAtlasBatch;
+
+    fn render<'w>(
+        _: &P,
+        view: Entity,
+        batch: Option<&'w ImposterAtlasBatch>,
+        (gpu_atlas, gpu_indirect): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(batch) = batch else {
+            return RenderCommandResult::Skip;
+        };
+
+        let gpu_atlas = gpu_atlas.into_inner();
+        let bind_groups = if batch.indirect {
+            gpu_indirect
+                .map(|gpu_indirect| gpu_indirect.into_inner())
+                .and_then(|gpu_indirect| {
+                    let group_1 = if batch.culled {
+                        gpu_indirect.views.get(&view)?.visible_bind_group.as_ref()
+                    } else {
+                        gpu_atlas.empty_bind_group.as_ref()
+                    };
+                    Some((group_1, gpu_indirect.atlas_bind_group.as_ref()))
+                })
+        } else {
+            Some((
+                gpu_atlas.empty_bind_group.as_ref(),
+                gpu_atlas.bind_group.as_ref(),
+            ))
+        };
+
+        let Some((Some(group_1), Some(group_2))) = bind_groups else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_bind_group(1, group_1, &[]);
+        pass.set_bind_group(2, group_2, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct DrawImposterAtlasBatch;
+impl<P: PhaseItem> RenderCommand<P> for DrawImposterAtlasBatch {
+    type Param = Option<SRes<GpuImposterIndirect>>;
+    type ViewQuery = Entity;
+    type ItemQuery = &'static ImposterAtlasBatch;
+
+    fn render<'w>(
+        _: &P,
+        view: Entity,
+        batch: Option<&'w ImposterAtlasBatch>,
+        gpu_indirect: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(batch) = batch else {
+            return RenderCommandResult::Skip;
+        };
+
+        if batch.culled {
+            let Some(view) =
+                gpu_indirect.and_then(|gpu_indirect| gpu_indirect.into_inner().views.get(&view))
+            else {
+                return RenderCommandResult::Skip;
+            };
+            // one set of draw args per grid mode
+            pass.draw_indirect(
+                &view.draws,
+                (batch.flags & GRID_MASK) as u64 * DRAW_INDIRECT_ARGS_SIZE,
+            );
+        } else {
+            pass.draw(0..6, batch.range.clone());
+        }
+        RenderCommandResult::Success
+    }
+}
+
+pub struct DrawImposterAtlasItem;
+impl<P: PhaseItem> RenderCommand<P> for DrawImposterAtlasItem {
+    type Param = ();
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _: (),
+        _: Option<()>,
+        _: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        pass.draw(0..6, item.batch_range().clone());
+        RenderCommandResult::Success
+    }
+}
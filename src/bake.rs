@@ -9,19 +9,24 @@ use std::{
 
 use bevy::{
     asset::load_internal_asset,
+    core::FrameCount,
     core_pipeline::{
         core_3d::{AlphaMask3d, Opaque3d, Opaque3dBinKey, Transparent3d},
         fullscreen_vertex_shader::fullscreen_shader_vertex_state,
         prepass::OpaqueNoLightmap3dBinKey,
     },
-    ecs::{entity::EntityHashSet, query::QueryFilter, system::lifetimeless::SRes},
+    ecs::{
+        entity::{Entities, EntityHashMap, EntityHashSet},
+        query::QueryFilter,
+        system::{lifetimeless::SRes, SystemParam},
+    },
     image::{ImageSampler, TextureFormatPixelInfo},
     pbr::{
         alpha_mode_pipeline_key, graph::NodePbr, prepare_preprocess_bind_groups, DrawMesh,
         ExtendedMaterial, GpuPreprocessNode, MaterialExtension, MaterialPipelineKey, MeshPipeline,
         MeshPipelineKey, PreparedMaterial, PrepassPipeline, PreprocessBindGroup,
-        RenderMaterialInstances, RenderMeshInstances, SetMaterialBindGroup, SetMeshBindGroup,
-        SetPrepassViewBindGroup, SkipGpuPreprocess,
+        RenderMaterialInstances, RenderMeshInstances, SetMeshBindGroup, SetPrepassViewBindGroup,
+        SkipGpuPreprocess,
     },
     prelude::*,
     render::{
@@ -44,12 +49,14 @@ use bevy::{
             BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
             BufferDescriptor, CachedRenderPipelineId, ColorTargetState, ColorWrites,
             CommandEncoderDescriptor, Extent3d, FragmentState, PipelineCache, RenderPassDescriptor,
-            RenderPipelineDescriptor, ShaderDefVal, ShaderRef, ShaderType, SpecializedMeshPipeline,
+            RenderPipelineDescriptor, ShaderDefVal, ShaderRef, SpecializedMeshPipeline,
             SpecializedMeshPipelines, StoreOp, Texture, TextureDescriptor, TextureDimension,
             TextureFormat, TextureUsages, UniformBuffer,
         },
         renderer::{RenderDevice, RenderQueue},
-        sync_world::{MainEntity, RenderEntity, SyncToRenderWorld, TemporaryRenderEntity},
+        sync_world::{
+            MainEntity, MainEntityHashMap, RenderEntity, SyncToRenderWorld, TemporaryRenderEntity,
+        },
         texture::{ColorAttachment, GpuImage, TextureCache},
         view::{
             ColorGrading, ExtractedView, NoFrustumCulling, RenderLayers, RenderVisibleEntities,
@@ -63,7 +70,11 @@ use bevy::{
 use wgpu::{BufferUsages, ImageCopyBuffer, ImageDataLayout, ShaderStages};
 
 use crate::{
-    asset_loader::write_asset,
+    asset_loader::{write_asset, write_filterable_asset},
+    bake_target::{
+        fit_bake_targets, ImposterBakeTarget, ImposterBakeTargetFitted, ImposterBakeTargetResolved,
+    },
+    bake_task::resolve_bake_tasks,
     oct_coords::{normal_from_grid, GridMode},
     ImposterRenderPlugin,
 };
@@ -119,9 +130,23 @@ impl Plugin for ImposterBakePlugin {
             (
                 check_imposter_visibility::<With<Mesh3d>>
                     .in_set(VisibilitySystems::CheckVisibility),
+                fit_bake_targets
+                    .after(TransformSystem::TransformPropagate)
+                    .after(VisibilitySystems::CalculateBounds)
+                    .before(VisibilitySystems::CheckVisibility),
                 check_finished_cameras,
+                resolve_bake_tasks.after(check_finished_cameras),
+                check_unsupported_materials.after(CountExpectedImposterMaterials),
             ),
-        );
+        )
+        .init_resource::<ImposterBakeFallback>()
+        .add_event::<ImposterBakeUnsupported>()
+        .add_event::<ImposterBakeStarted>()
+        .add_event::<ImposterBakeTargetFitted>()
+        .add_event::<ImposterBakeProgress>()
+        .add_event::<ImposterBakeWaiting>()
+        .add_event::<ImposterBakeFinished>()
+        .add_event::<ImposterBakeFailed>();
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
@@ -137,7 +162,11 @@ impl Plugin for ImposterBakePlugin {
             .init_resource::<ImposterActualRenderCount>()
             .init_resource::<ImpostersBaked>()
             .init_resource::<PartBaked>()
-            .add_systems(ExtractSchedule, extract_imposter_cameras)
+            .init_resource::<ImposterBakeFallbacks>()
+            .add_systems(
+                ExtractSchedule,
+                (extract_imposter_cameras, extract_imposter_fallbacks),
+            )
             .add_systems(
                 Render,
                 (
@@ -218,7 +247,9 @@ where
     fn build(&self, app: &mut App) {
         app.add_systems(
             PostUpdate,
-            count_expected_imposter_materials::<M>.after(check_imposter_visibility::<With<Mesh3d>>),
+            count_expected_imposter_materials::<M>
+                .in_set(CountExpectedImposterMaterials)
+                .after(check_imposter_visibility::<With<Mesh3d>>),
         );
     }
 
@@ -250,11 +281,88 @@ impl<B: Material, E: MaterialExtension + ImposterBakeMaterialExtension> Imposter
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum BakeState {
     Rendering,
     RunningCallback,
     Finished,
+    // the bake was abandoned, see `ImposterBakeCamera::timeout`. continuous cameras go back to `Rendering`
+    // and start over on the next frame
+    Failed(String),
+}
+
+/// how long a bake may go without rendering tiles before it fails
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BakeTimeout {
+    Frames(u32),
+    Seconds(f32),
+}
+
+// counts the entities each bake camera waits for
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CountExpectedImposterMaterials;
+
+/// bakes meshes whose material type has no `ImposterBakeMaterialPlugin` with this material (e.g. a flat unlit
+/// color) rather than leaving them out. unset by default. the fallbacks are drawn by the
+/// `ImposterBakeMaterialPlugin::<StandardMaterial>` that the bake plugin adds
+#[derive(Resource, Default, Clone)]
+pub struct ImposterBakeFallback(pub Option<Handle<StandardMaterial>>);
+
+/// sent when meshes in range have a material type without an `ImposterBakeMaterialPlugin`. each entity is
+/// reported once per camera
+#[derive(Event, Debug, Clone)]
+pub struct ImposterBakeUnsupported {
+    pub camera: Entity,
+    pub entities: Vec<Entity>,
+    /// whether they are baked with the `ImposterBakeFallback`, or left out
+    pub fallback: bool,
+}
+
+/// sent when a bake camera starts a bake (each cycle for continuous cameras)
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ImposterBakeStarted {
+    pub camera: Entity,
+}
+
+/// sent when more tiles have been rendered
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ImposterBakeProgress {
+    pub camera: Entity,
+    pub rendered: u32,
+    pub total: u32,
+}
+
+/// sent each frame a bake is held back because not all entities in range rendered, usually
+/// while pipelines compile or meshes and materials are still being prepared
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ImposterBakeWaiting {
+    pub camera: Entity,
+    /// entities in range with a bakeable material
+    pub expected: usize,
+    /// entities that rendered
+    pub actual: usize,
+}
+
+/// sent when a bake completes, after the callback (if any) has run
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ImposterBakeFinished {
+    pub camera: Entity,
+}
+
+/// sent when a bake can't complete, e.g. when the baked image couldn't be read back
+#[derive(Event, Debug, Clone)]
+pub struct ImposterBakeFailed {
+    pub camera: Entity,
+    pub reason: String,
+}
+
+// progress reports from the render world. dropped if the main world falls behind, except failures
+#[derive(Debug, Clone)]
+pub enum BakeProgress {
+    // the entities that did render are kept in `ImposterBakeCompleteChannel::drawn`
+    Waiting { expected: usize, actual: usize },
+    Tiles { rendered: u32, total: u32 },
+    Failed(String),
 }
 
 #[derive(Component, Clone)]
@@ -287,6 +395,9 @@ pub struct ImposterBakeCamera {
     pub wait_for_render: bool,
     // max number of tiles to render in a single frame
     pub max_tiles_per_frame: usize,
+    // optional limit on frames or seconds without rendering any tiles. when exceeded the state becomes `BakeState::Failed`,
+    // listing what didn't render. continuous cameras then start over on the next frame
+    pub timeout: Option<BakeTimeout>,
     // signal for completion (if not continuous) - written by the library
     pub state: BakeState,
     // optional callback for completion
@@ -309,6 +420,7 @@ impl Default for ImposterBakeCamera {
             continuous: false,
             wait_for_render: true,
             max_tiles_per_frame: usize::MAX,
+            timeout: None,
             state: BakeState::Rendering,
             callback: None,
             manual_camera_transforms: None,
@@ -335,6 +447,7 @@ impl ImposterBakeCamera {
                 sample_count: 1,
                 usage: TextureUsages::TEXTURE_BINDING
                     | TextureUsages::COPY_DST
+                    | TextureUsages::COPY_SRC
                     | TextureUsages::RENDER_ATTACHMENT,
                 view_formats: &[],
             },
@@ -346,6 +459,20 @@ impl ImposterBakeCamera {
         self.target = Some(images.add(image));
     }
 
+    // the view used to capture a tile, looking at `center` from the edge of the capture sphere
+    pub fn tile_view_transform(&self, center: Vec3, tile: UVec2) -> GlobalTransform {
+        if let Some(camera_transforms) = self.manual_camera_transforms.as_ref() {
+            *camera_transforms
+                .get((tile.y * self.grid_size + tile.x) as usize)
+                .expect("not enough manual camera transforms")
+        } else {
+            let (normal, up) = normal_from_grid(tile, self.grid_mode, self.grid_size);
+            GlobalTransform::from(
+                Transform::from_translation(center + normal * self.radius).looking_at(center, up),
+            )
+        }
+    }
+
     // add a callback to be run on completion
     pub fn set_callback(&mut self, callback: impl FnOnce(Image) + Send + Sync + 'static) {
         self.callback = Some(Arc::new(Mutex::new(Some(Box::new(callback)))));
@@ -392,20 +519,64 @@ impl ImposterBakeCamera {
             }
         }
     }
+
+    // as `save_asset_callback`, but stores the material in separate hardware-filterable textures rather than
+    // bit-packed integers. `compress` block compresses them (bc7 / bc5), which the target gpu must support.
+    pub fn save_filterable_asset_callback(
+        &self,
+        path: impl AsRef<Path>,
+        shrink_asset: bool,
+        compress: bool,
+    ) -> impl FnOnce(bevy::prelude::Image) + Send + Sync + 'static {
+        let mut path = path.as_ref().to_owned();
+        if path.extension() != Some(OsStr::new("boimp")) {
+            path.set_extension("boimp");
+        }
+
+        let grid_size = self.grid_size;
+        let tile_size = self.tile_size;
+        let radius = self.radius;
+        let mode = self.grid_mode;
+        move |image| {
+            if let Err(e) = write_filterable_asset(
+                &path,
+                radius,
+                grid_size,
+                tile_size,
+                mode,
+                image,
+                shrink_asset,
+                compress,
+            ) {
+                error!("error writing imposter asset: {e}");
+            } else {
+                info!("imposter saved");
+            }
+        }
+    }
 }
 
 #[derive(Component)]
 pub struct ImposterBakeCompleteChannel {
     sender: crossbeam_channel::Sender<BakeState>,
     receiver: Option<crossbeam_channel::Receiver<BakeState>>,
+    progress_sender: crossbeam_channel::Sender<BakeProgress>,
+    progress_receiver: crossbeam_channel::Receiver<BakeProgress>,
+    // main world entities drawn in the latest attempt that was held back. overwritten in place by the render
+    // world, and only read when the bake times out
+    drawn: Arc<Mutex<Vec<Entity>>>,
 }
 
 impl Default for ImposterBakeCompleteChannel {
     fn default() -> Self {
         let (sender, receiver) = crossbeam_channel::bounded(2); // make sure we don't block rendering
+        let (progress_sender, progress_receiver) = crossbeam_channel::bounded(16);
         Self {
             sender,
             receiver: Some(receiver),
+            progress_sender,
+            progress_receiver,
+            drawn: Arc::default(),
         }
     }
 }
@@ -452,7 +623,8 @@ pub fn check_imposter_visibility<QF>(
     {
         visible_entities.clear::<QF>();
 
-        if !camera.continuous && camera.state == BakeState::Finished {
+        if !camera.continuous && matches!(camera.state, BakeState::Finished | BakeState::Failed(_))
+        {
             return;
         }
 
@@ -503,7 +675,8 @@ pub fn check_imposter_visibility<QF>(
         );
 
         thread_queues.drain_into(visible_entities.get_mut::<QF>());
-        expected_count.0 = 0;
+        expected_count.0.clear();
+        expected_count.1.clear();
     }
 }
 
@@ -513,19 +686,115 @@ fn count_expected_imposter_materials<M: ImposterBakeMaterial>(
     materials: Query<(), (With<MeshMaterial3d<M>>, With<Mesh3d>)>,
 ) {
     for (mut count, visible_entities) in q.iter_mut() {
-        let material_count = visible_entities
-            .iter::<With<Mesh3d>>()
-            .filter(|e| materials.get(**e).is_ok())
-            .count();
-        count.0 += material_count;
+        let before = count.0.len();
+        count.0.extend(
+            visible_entities
+                .iter::<With<Mesh3d>>()
+                .filter(|e| materials.get(**e).is_ok())
+                .map(|e| (*e, M::short_type_path())),
+        );
         debug!(
             "bake entities {}: {}",
             std::any::type_name::<M>(),
-            material_count
+            count.0.len() - before
         );
     }
 }
 
+fn check_unsupported_materials(
+    mut q: Query<
+        (Entity, &VisibleEntities, &mut ImposterExpectedRenderCount),
+        With<ImposterBakeCamera>,
+    >,
+    fallback: Res<ImposterBakeFallback>,
+    mut events: EventWriter<ImposterBakeUnsupported>,
+    mut reported: Local<EntityHashMap<EntityHashSet>>,
+    entities: &Entities,
+) {
+    reported.retain(|ent, _| q.contains(*ent));
+    for reported in reported.values_mut() {
+        reported.retain(|entity| entities.contains(*entity));
+    }
+    let use_fallback = fallback.0.is_some();
+
+    for (camera, visible_entities, mut expected) in q.iter_mut() {
+        let supported = expected
+            .0
+            .iter()
+            .map(|(entity, _)| *entity)
+            .collect::<EntityHashSet>();
+        let unsupported = visible_entities
+            .iter::<With<Mesh3d>>()
+            .filter(|entity| !supported.contains(*entity))
+            .copied()
+            .collect::<Vec<_>>();
+
+        if use_fallback {
+            expected.0.extend(
+                unsupported
+                    .iter()
+                    .map(|entity| (*entity, "StandardMaterial (fallback)")),
+            );
+            expected.1.extend(unsupported.iter().copied());
+        }
+
+        let reported = reported.entry(camera).or_default();
+        let new = unsupported
+            .into_iter()
+            .filter(|entity| reported.insert(*entity))
+            .collect::<Vec<_>>();
+        if !new.is_empty() {
+            let action = if use_fallback {
+                "baking with the fallback material"
+            } else {
+                "leaving them out (set an `ImposterBakeFallback` to include them)"
+            };
+            warn!(
+                "imposter bake: {} entities in range have no `ImposterBakeMaterialPlugin` for their material, {action}: {:?}",
+                new.len(),
+                new
+            );
+            events.send(ImposterBakeUnsupported {
+                camera,
+                entities: new,
+                fallback: use_fallback,
+            });
+        }
+    }
+}
+
+// fallback materials for entities without a bakeable material of their own
+#[derive(Resource, Default)]
+pub struct ImposterBakeFallbacks(MainEntityHashMap<AssetId<StandardMaterial>>);
+
+impl ImposterBakeFallbacks {
+    // only the `StandardMaterial` plugin finds fallbacks
+    fn get<M: Material>(&self, entity: &MainEntity) -> Option<AssetId<M>> {
+        self.0.get(entity)?.untyped().try_typed::<M>().ok()
+    }
+}
+
+fn extract_imposter_fallbacks(
+    mut fallbacks: ResMut<ImposterBakeFallbacks>,
+    fallback: Extract<Res<ImposterBakeFallback>>,
+    cameras: Extract<Query<(&ImposterBakeCamera, &ImposterExpectedRenderCount)>>,
+) {
+    fallbacks.0.clear();
+    let Some(material) = fallback.0.as_ref() else {
+        return;
+    };
+    for (camera, expected) in cameras.iter() {
+        if camera.state == BakeState::Rendering {
+            fallbacks.0.extend(
+                expected
+                    .1
+                    .iter()
+                    .map(|e| (MainEntity::from(*e), material.id())),
+            );
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct ExtractedImposterBakeCamera {
     pub grid_size: u32,
@@ -537,6 +806,8 @@ pub struct ExtractedImposterBakeCamera {
     pub wait_for_render: bool,
     pub max_tiles_per_frame: usize,
     pub channel: crossbeam_channel::Sender<BakeState>,
+    pub progress: crossbeam_channel::Sender<BakeProgress>,
+    pub drawn: Arc<Mutex<Vec<Entity>>>,
     pub callback: Option<ImageCallback>,
 }
 
@@ -614,44 +885,197 @@ impl<T: CachedRenderPipelinePhaseItem> CachedRenderPipelinePhaseItem for Imposte
     }
 }
 
+#[derive(SystemParam)]
+struct BakeEventWriters<'w> {
+    started: EventWriter<'w, ImposterBakeStarted>,
+    progress: EventWriter<'w, ImposterBakeProgress>,
+    waiting: EventWriter<'w, ImposterBakeWaiting>,
+    finished: EventWriter<'w, ImposterBakeFinished>,
+    failed: EventWriter<'w, ImposterBakeFailed>,
+}
+
+// bookkeeping for a camera's current bake
+struct BakeTracking {
+    // when the bake started, or last rendered tiles
+    progress_frame: u32,
+    progress_secs: f32,
+}
+
+#[allow(clippy::type_complexity)]
 fn check_finished_cameras(
     mut commands: Commands,
     mut q: Query<(
         Entity,
         &mut ImposterBakeCamera,
         &ImposterBakeCompleteChannel,
+        &ImposterExpectedRenderCount,
+        &VisibleEntities,
     )>,
+    mut events: BakeEventWriters,
+    mut started: Local<EntityHashMap<BakeTracking>>,
+    frame: Res<FrameCount>,
+    time: Res<Time<Real>>,
 ) {
-    for (ent, mut cam, receiver) in q.iter_mut() {
+    started.retain(|ent, _| q.contains(*ent));
+
+    for (ent, mut cam, receiver, expected, visible_entities) in q.iter_mut() {
+        // continuous cameras retry after failing
+        if cam.continuous && matches!(cam.state, BakeState::Failed(_)) {
+            cam.state = BakeState::Rendering;
+        }
+
+        if cam.state == BakeState::Rendering && !started.contains_key(&ent) {
+            started.insert(
+                ent,
+                BakeTracking {
+                    progress_frame: frame.0,
+                    progress_secs: time.elapsed_secs(),
+                },
+            );
+            receiver.drawn.lock().unwrap().clear();
+            events.started.send(ImposterBakeStarted { camera: ent });
+        }
+
         while let Some(new_state) = receiver.receiver.as_ref().and_then(|r| r.try_recv().ok()) {
+            // a failed bake was abandoned, so later updates from the render world are stale
+            if matches!(cam.state, BakeState::Failed(_)) {
+                continue;
+            }
+
+            let finished = new_state == BakeState::Finished;
+            if finished {
+                // continuous cameras start over on the next frame
+                started.remove(&ent);
+                events.finished.send(ImposterBakeFinished { camera: ent });
+            }
+
             if !cam.continuous {
                 debug!("recv state: {new_state:?}");
                 cam.state = new_state;
 
-                if new_state == BakeState::Finished {
+                if finished {
                     commands.entity(ent).remove::<ImposterBakeCompleteChannel>();
                 }
             }
         }
+
+        while let Ok(progress) = receiver.progress_receiver.try_recv() {
+            match progress {
+                BakeProgress::Waiting { expected, actual } => {
+                    events.waiting.send(ImposterBakeWaiting {
+                        camera: ent,
+                        expected,
+                        actual,
+                    });
+                }
+                BakeProgress::Tiles { rendered, total } => {
+                    if let Some(tracking) = started.get_mut(&ent) {
+                        tracking.progress_frame = frame.0;
+                        tracking.progress_secs = time.elapsed_secs();
+                    }
+                    events.progress.send(ImposterBakeProgress {
+                        camera: ent,
+                        rendered,
+                        total,
+                    });
+                }
+                BakeProgress::Failed(reason) => {
+                    fail_bake(ent, &mut cam, reason, &mut events);
+                    started.remove(&ent);
+                }
+            }
+        }
+
+        let (Some(timeout), Some(tracking)) = (cam.timeout, started.get(&ent)) else {
+            continue;
+        };
+        if cam.state != BakeState::Rendering {
+            continue;
+        }
+        let timed_out = match timeout {
+            BakeTimeout::Frames(frames) => frame.0.wrapping_sub(tracking.progress_frame) > frames,
+            BakeTimeout::Seconds(secs) => time.elapsed_secs() - tracking.progress_secs > secs,
+        };
+        if timed_out {
+            let reason =
+                describe_unrendered(expected, visible_entities, &receiver.drawn.lock().unwrap());
+            fail_bake(ent, &mut cam, reason, &mut events);
+            started.remove(&ent);
+        }
+    }
+}
+
+fn fail_bake(
+    ent: Entity,
+    cam: &mut ImposterBakeCamera,
+    reason: String,
+    events: &mut BakeEventWriters,
+) {
+    warn!("imposter bake failed: {reason}");
+    cam.state = BakeState::Failed(reason.clone());
+    events.failed.send(ImposterBakeFailed {
+        camera: ent,
+        reason,
+    });
+}
+
+// lists the entities in range that kept the bake waiting
+fn describe_unrendered(
+    expected: &ImposterExpectedRenderCount,
+    visible_entities: &VisibleEntities,
+    drawn: &[Entity],
+) -> String {
+    let drawn = drawn.iter().copied().collect::<EntityHashSet>();
+    let expected_entities = expected
+        .0
+        .iter()
+        .map(|(entity, _)| *entity)
+        .collect::<EntityHashSet>();
+
+    let not_drawn = expected
+        .0
+        .iter()
+        .filter(|(entity, _)| !drawn.contains(entity))
+        .map(|(entity, material)| format!("{entity} ({material})"))
+        .collect::<Vec<_>>();
+    let unregistered = visible_entities
+        .iter::<With<Mesh3d>>()
+        .filter(|entity| !expected_entities.contains(*entity))
+        .map(|entity| entity.to_string())
+        .collect::<Vec<_>>();
+
+    let mut reason = format!(
+        "timed out waiting for render, {}/{} entities drawn",
+        expected.0.len() - not_drawn.len(),
+        expected.0.len()
+    );
+    if !not_drawn.is_empty() {
+        reason += &format!(". not drawn: {}", not_drawn.join(", "));
     }
+    if !unregistered.is_empty() {
+        reason += &format!(
+            ". in range without an `ImposterBakeMaterialPlugin` for their material (ignored): {}",
+            unregistered.join(", ")
+        );
+    }
+    reason
 }
 
 pub type ImageCallback = Arc<Mutex<Option<Box<dyn FnOnce(Image) + Send + Sync + 'static>>>>;
 
+// image size, callback, state and progress channels, and the buffer to read back from
+pub type BakedImageTransfer = (
+    u32,
+    ImageCallback,
+    crossbeam_channel::Sender<BakeState>,
+    crossbeam_channel::Sender<BakeProgress>,
+    Buffer,
+);
+
 #[derive(Resource)]
 pub struct ImpostersBaked {
-    sender: crossbeam_channel::Sender<(
-        u32,
-        ImageCallback,
-        crossbeam_channel::Sender<BakeState>,
-        Buffer,
-    )>,
-    receiver: crossbeam_channel::Receiver<(
-        u32,
-        ImageCallback,
-        crossbeam_channel::Sender<BakeState>,
-        Buffer,
-    )>,
+    sender: crossbeam_channel::Sender<BakedImageTransfer>,
+    receiver: crossbeam_channel::Receiver<BakedImageTransfer>,
 }
 
 impl Default for ImpostersBaked {
@@ -676,6 +1100,8 @@ pub fn extract_imposter_cameras(
             &ImposterExpectedRenderCount,
             Ref<GlobalTransform>,
             &VisibleEntities,
+            Has<ImposterBakeTarget>,
+            Option<&ImposterBakeTargetResolved>,
         )>,
     >,
     mapper: Extract<Query<&RenderEntity>>,
@@ -684,9 +1110,13 @@ pub fn extract_imposter_cameras(
     let mut entities = EntityHashSet::default();
     let mut prev_cache = std::mem::take(&mut *subview_cache);
 
-    for (entity, camera, channel, expected_count, gt, visible_entities) in cameras.iter() {
+    for (entity, camera, channel, expected_count, gt, visible_entities, targeted, resolved) in
+        cameras.iter()
+    {
+        // wait for the target's bounds
         if camera.state != BakeState::Rendering
-            || !channel.receiver.as_ref().map_or(true, |r| r.is_empty())
+            || (targeted && !resolved.is_some_and(|r| r.0))
+            || channel.receiver.as_ref().is_some_and(|r| !r.is_empty())
         {
             commands.entity(entity).remove::<(
                 ExtractedImposterBakeCamera,
@@ -723,20 +1153,7 @@ pub fn extract_imposter_cameras(
             let clip_from_view = projection.get_clip_from_view();
             for y in 0..camera.grid_size {
                 for x in 0..camera.grid_size {
-                    let camera_transform = if let Some(camera_transforms) =
-                        camera.manual_camera_transforms.as_ref()
-                    {
-                        *camera_transforms
-                            .get((y * camera.grid_size + x) as usize)
-                            .expect("not enough manual camera transforms")
-                    } else {
-                        let (normal, up) =
-                            normal_from_grid(UVec2::new(x, y), camera.grid_mode, camera.grid_size);
-                        GlobalTransform::from(
-                            Transform::from_translation(center + normal * camera.radius)
-                                .looking_at(center, up),
-                        )
-                    };
+                    let camera_transform = camera.tile_view_transform(center, UVec2::new(x, y));
 
                     let view = ExtractedView {
                         clip_from_view,
@@ -792,10 +1209,12 @@ pub fn extract_imposter_cameras(
                 target: camera.target.clone(),
                 multisample: camera.multisample,
                 subviews,
-                expected_count: expected_count.0,
+                expected_count: expected_count.0.len(),
                 wait_for_render: camera.wait_for_render,
                 max_tiles_per_frame: camera.max_tiles_per_frame,
                 channel: channel.sender.clone(),
+                progress: channel.progress_sender.clone(),
+                drawn: channel.drawn.clone(),
                 callback: camera.callback.clone(),
             },
             ExtractedCamera {
@@ -968,10 +1387,17 @@ where
     }
 }
 
-#[derive(ShaderType)]
-pub struct BlitUniform {
-    samples: u32,
+// see `render::uniform`
+#[allow(dead_code)]
+mod uniform {
+    use bevy::render::render_resource::ShaderType;
+
+    #[derive(ShaderType)]
+    pub struct BlitUniform {
+        pub(super) samples: u32,
+    }
 }
+pub use uniform::BlitUniform;
 
 #[derive(Resource)]
 pub struct ImposterBlitPipeline {
@@ -1169,6 +1595,7 @@ pub fn queue_imposter_material_meshes<M: ImposterBakeMaterial>(
     render_mesh_instances: Res<RenderMeshInstances>,
     render_materials: Res<RenderAssets<PreparedMaterial<M>>>,
     render_material_instances: Res<RenderMaterialInstances<M>>,
+    fallbacks: Res<ImposterBakeFallbacks>,
     // render_lightmaps: Res<RenderLightmaps>,
 ) where
     M::Data: PartialEq + Eq + Hash + Clone,
@@ -1198,14 +1625,18 @@ pub fn queue_imposter_material_meshes<M: ImposterBakeMaterial>(
         let view_key = MeshPipelineKey::from_msaa_samples(1);
 
         for (render_entity, visible_entity) in visible_entities.iter::<With<Mesh3d>>() {
-            let Some(material_asset_id) = render_material_instances.get(visible_entity) else {
+            let Some(material_asset_id) = render_material_instances
+                .get(visible_entity)
+                .copied()
+                .or_else(|| fallbacks.get::<M>(visible_entity))
+            else {
                 continue;
             };
             let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(*visible_entity)
             else {
                 continue;
             };
-            let Some(material) = render_materials.get(*material_asset_id) else {
+            let Some(material) = render_materials.get(material_asset_id) else {
                 continue;
             };
             let Some(mesh) = render_meshes.get(mesh_instance.mesh_asset_id) else {
@@ -1334,6 +1765,7 @@ impl ViewNode for ImposterBakeNode {
         };
 
         let actual = world.resource::<ImposterActualRenderCount>();
+        let actual_entities = &actual.2;
 
         let part_baked = world.resource::<PartBaked>();
 
@@ -1344,6 +1776,7 @@ impl ViewNode for ImposterBakeNode {
             let _parallel_lock = actual.1.lock().unwrap();
             let mut part_baked = part_baked.0.lock().unwrap();
             *actual.0.lock().unwrap() = 0;
+            actual.2.lock().unwrap().clear();
 
             let mut command_encoder =
                 render_device.create_command_encoder(&CommandEncoderDescriptor {
@@ -1399,6 +1832,15 @@ impl ViewNode for ImposterBakeNode {
 
                     if (!ok || (actual != camera.expected_count)) && camera.wait_for_render {
                         debug!("not ready: {}/{}", actual, camera.expected_count);
+                        camera
+                            .drawn
+                            .lock()
+                            .unwrap()
+                            .clone_from(&actual_entities.lock().unwrap());
+                        let _ = camera.progress.try_send(BakeProgress::Waiting {
+                            expected: camera.expected_count,
+                            actual,
+                        });
                     } else {
                         rendered += 1;
                     }
@@ -1468,6 +1910,15 @@ impl ViewNode for ImposterBakeNode {
                         if !success {
                             debug!("not ready: {}/{}", actual, camera.expected_count);
                             if camera.wait_for_render {
+                                camera
+                                    .drawn
+                                    .lock()
+                                    .unwrap()
+                                    .clone_from(&actual_entities.lock().unwrap());
+                                let _ = camera.progress.try_send(BakeProgress::Waiting {
+                                    expected: camera.expected_count,
+                                    actual,
+                                });
                                 break;
                             }
                         }
@@ -1504,7 +1955,13 @@ impl ViewNode for ImposterBakeNode {
                 }
             }
 
-            part_baked.insert(view, rendered);
+            let previously_rendered = part_baked.insert(view, rendered).unwrap_or_default();
+            if rendered > previously_rendered {
+                let _ = camera.progress.try_send(BakeProgress::Tiles {
+                    rendered: rendered as u32,
+                    total: camera.grid_size * camera.grid_size,
+                });
+            }
             debug!(
                 "{:?} -> {}/{}",
                 view,
@@ -1558,6 +2015,7 @@ impl ViewNode for ImposterBakeNode {
                         camera.tile_size * camera.grid_size,
                         callback.clone(),
                         camera.channel.clone(),
+                        camera.progress.clone(),
                         buffer,
                     ));
                 } else {
@@ -1590,7 +2048,9 @@ impl ViewNode for ImposterBakeNode {
 }
 
 pub fn copy_back(baked: Res<ImpostersBaked>) {
-    while let Ok((image_size, callback, success_channel, buffer)) = baked.receiver.try_recv() {
+    while let Ok((image_size, callback, success_channel, progress_channel, buffer)) =
+        baked.receiver.try_recv()
+    {
         debug!("begin async process");
 
         let Some(callback) = callback.lock().unwrap().take() else {
@@ -1603,13 +2063,15 @@ pub fn copy_back(baked: Res<ImpostersBaked>) {
             let buffer_slice = buffer.slice(..);
             // The polling for this map call is done every frame when the command queue is submitted.
             buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-                let err = result.err();
-                if err.is_some() {
-                    panic!("{}", err.unwrap().to_string());
-                }
-                tx.try_send(()).unwrap();
+                tx.try_send(result).unwrap();
             });
-            rx.recv().await.unwrap();
+            if let Err(err) = rx.recv().await.unwrap() {
+                // blocking is fine off the render thread, and failures must not be dropped
+                let _ = progress_channel.send(BakeProgress::Failed(format!(
+                    "failed to read back the baked image: {err}"
+                )));
+                return;
+            }
             let data = buffer_slice.get_mapped_range();
             // we immediately move the data to CPU memory to avoid holding the mapped view for long
             let mut result = Vec::from(&*data);
@@ -1667,11 +2129,12 @@ pub fn get_aligned_size(width: u32, height: u32, pixel_size: u32) -> u32 {
     height * align_byte_size(width * pixel_size)
 }
 
+// entities with their material type, and the entities among them using the fallback material
 #[derive(Component, Default, Clone)]
-pub struct ImposterExpectedRenderCount(usize);
+pub struct ImposterExpectedRenderCount(Vec<(Entity, &'static str)>, Vec<Entity>);
 
 #[derive(Resource, Default)]
-pub struct ImposterActualRenderCount(Arc<Mutex<usize>>, Arc<Mutex<()>>);
+pub struct ImposterActualRenderCount(Arc<Mutex<usize>>, Arc<Mutex<()>>, Arc<Mutex<Vec<Entity>>>);
 
 pub struct CountRenderCommand;
 impl<P: PhaseItem> RenderCommand<P> for CountRenderCommand {
@@ -1682,13 +2145,54 @@ impl<P: PhaseItem> RenderCommand<P> for CountRenderCommand {
     type ItemQuery = ();
 
     fn render<'w>(
-        _: &P,
+        item: &P,
         _: bevy::ecs::query::ROQueryItem<'w, Self::ViewQuery>,
         _: Option<bevy::ecs::query::ROQueryItem<'w, Self::ItemQuery>>,
         count: bevy::ecs::system::SystemParamItem<'w, '_, Self::Param>,
         _: &mut TrackedRenderPass<'w>,
     ) -> bevy::render::render_phase::RenderCommandResult {
         *count.0.lock().unwrap() += 1;
+        count.2.lock().unwrap().push(item.main_entity().id());
+        bevy::render::render_phase::RenderCommandResult::Success
+    }
+}
+
+// `SetMaterialBindGroup`, also checking for fallbacks
+pub struct SetImposterBakeMaterialBindGroup<M: Material, const I: usize>(PhantomData<M>);
+impl<P: PhaseItem, M: Material, const I: usize> RenderCommand<P>
+    for SetImposterBakeMaterialBindGroup<M, I>
+{
+    type Param = (
+        SRes<RenderAssets<PreparedMaterial<M>>>,
+        SRes<RenderMaterialInstances<M>>,
+        SRes<ImposterBakeFallbacks>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _: bevy::ecs::query::ROQueryItem<'w, Self::ViewQuery>,
+        _: Option<bevy::ecs::query::ROQueryItem<'w, Self::ItemQuery>>,
+        (materials, material_instances, fallbacks): bevy::ecs::system::SystemParamItem<
+            'w,
+            '_,
+            Self::Param,
+        >,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> bevy::render::render_phase::RenderCommandResult {
+        let materials = materials.into_inner();
+        let Some(material_asset_id) = material_instances
+            .get(&item.main_entity())
+            .copied()
+            .or_else(|| fallbacks.get::<M>(&item.main_entity()))
+        else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        let Some(material) = materials.get(material_asset_id) else {
+            return bevy::render::render_phase::RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, &material.bind_group, &[]);
         bevy::render::render_phase::RenderCommandResult::Success
     }
 }
@@ -1697,7 +2201,133 @@ pub type DrawImposter<M> = (
     SetItemPipeline,
     SetPrepassViewBindGroup<0>,
     SetMeshBindGroup<1>,
-    SetMaterialBindGroup<M, 2>,
+    SetImposterBakeMaterialBindGroup<M, 2>,
     DrawMesh,
     CountRenderCommand,
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failed_events(app: &App) -> usize {
+        app.world().resource::<Events<ImposterBakeFailed>>().len()
+    }
+
+    #[test]
+    fn continuous_cameras_retry_after_timing_out() {
+        let mut app = App::new();
+        app.add_event::<ImposterBakeStarted>()
+            .add_event::<ImposterBakeProgress>()
+            .add_event::<ImposterBakeWaiting>()
+            .add_event::<ImposterBakeFinished>()
+            .add_event::<ImposterBakeFailed>()
+            .init_resource::<FrameCount>()
+            .init_resource::<Time<Real>>()
+            .add_systems(Update, check_finished_cameras);
+
+        let timeout = Some(BakeTimeout::Frames(0));
+        let continuous = app
+            .world_mut()
+            .spawn(ImposterBakeCamera {
+                continuous: true,
+                timeout,
+                ..default()
+            })
+            .id();
+        let once = app
+            .world_mut()
+            .spawn(ImposterBakeCamera {
+                timeout,
+                ..default()
+            })
+            .id();
+        let state = |app: &App, entity| {
+            app.world()
+                .get::<ImposterBakeCamera>(entity)
+                .unwrap()
+                .state
+                .clone()
+        };
+
+        // nothing renders, so both time out on the next frame
+        app.update();
+        app.world_mut().resource_mut::<FrameCount>().0 += 1;
+        app.update();
+        assert!(matches!(state(&app, continuous), BakeState::Failed(_)));
+        assert!(matches!(state(&app, once), BakeState::Failed(_)));
+        assert_eq!(failed_events(&app), 2);
+
+        app.update();
+        assert_eq!(state(&app, continuous), BakeState::Rendering);
+        assert!(matches!(state(&app, once), BakeState::Failed(_)));
+
+        // and the retry times out again
+        app.world_mut().resource_mut::<FrameCount>().0 += 1;
+        app.update();
+        assert!(matches!(state(&app, continuous), BakeState::Failed(_)));
+    }
+
+    #[test]
+    fn rendered_tiles_restart_the_timeout() {
+        let mut app = App::new();
+        app.add_event::<ImposterBakeStarted>()
+            .add_event::<ImposterBakeProgress>()
+            .add_event::<ImposterBakeWaiting>()
+            .add_event::<ImposterBakeFinished>()
+            .add_event::<ImposterBakeFailed>()
+            .init_resource::<FrameCount>()
+            .init_resource::<Time<Real>>()
+            .add_systems(Update, check_finished_cameras);
+
+        let camera = app
+            .world_mut()
+            .spawn(ImposterBakeCamera {
+                timeout: Some(BakeTimeout::Frames(1)),
+                ..default()
+            })
+            .id();
+        let send = |app: &mut App, progress| {
+            let channel = app
+                .world()
+                .get::<ImposterBakeCompleteChannel>(camera)
+                .unwrap();
+            channel.progress_sender.send(progress).unwrap();
+        };
+        let next_frame = |app: &mut App| {
+            app.world_mut().resource_mut::<FrameCount>().0 += 1;
+            app.update();
+        };
+
+        app.update();
+        for _ in 0..3 {
+            next_frame(&mut app);
+            send(
+                &mut app,
+                BakeProgress::Tiles {
+                    rendered: 1,
+                    total: 4,
+                },
+            );
+        }
+        next_frame(&mut app);
+        assert_eq!(failed_events(&app), 0);
+
+        // stalled after rendering some tiles
+        next_frame(&mut app);
+        next_frame(&mut app);
+        assert_eq!(failed_events(&app), 1);
+
+        // a late state update doesn't replace the failure
+        let channel = app
+            .world()
+            .get::<ImposterBakeCompleteChannel>(camera)
+            .unwrap();
+        channel.sender.send(BakeState::RunningCallback).unwrap();
+        app.update();
+        assert!(matches!(
+            app.world().get::<ImposterBakeCamera>(camera).unwrap().state,
+            BakeState::Failed(_)
+        ));
+    }
+}
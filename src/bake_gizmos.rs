@@ -0,0 +1,120 @@
+use bevy::{
+    prelude::*,
+    render::{primitives::Aabb, view::VisibleEntities},
+};
+
+use crate::ImposterBakeCamera;
+
+/// draws gizmos for each [`ImposterBakeCamera`], to check the radius and grid mode before baking.
+/// configure with the [`ImposterBakeGizmos`] gizmo group.
+pub struct ImposterBakeGizmoPlugin;
+
+impl Plugin for ImposterBakeGizmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_gizmo_group::<ImposterBakeGizmos>().add_systems(
+            PostUpdate,
+            draw_bake_gizmos.after(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+#[derive(Reflect, GizmoConfigGroup)]
+pub struct ImposterBakeGizmos {
+    /// the capture sphere
+    pub sphere_color: Color,
+    /// an arrow per tile, from the tile's camera towards the center
+    pub directions: bool,
+    pub direction_color: Color,
+    /// the orthographic volume captured by each tile. busy for large grids
+    pub frusta: bool,
+    pub frustum_color: Color,
+    /// bounds of the entities the camera considers in range (see `check_imposter_visibility`)
+    pub in_range: bool,
+    pub in_range_color: Color,
+}
+
+impl Default for ImposterBakeGizmos {
+    fn default() -> Self {
+        Self {
+            sphere_color: Color::srgb(1.0, 1.0, 0.0),
+            directions: true,
+            direction_color: Color::srgb(0.0, 0.8, 1.0),
+            frusta: false,
+            frustum_color: Color::srgba(1.0, 0.5, 0.0, 0.3),
+            in_range: true,
+            in_range_color: Color::srgb(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+fn draw_bake_gizmos(
+    mut gizmos: Gizmos<ImposterBakeGizmos>,
+    cameras: Query<(&ImposterBakeCamera, &GlobalTransform, &VisibleEntities)>,
+    meshes: Query<(&GlobalTransform, Option<&Aabb>)>,
+) {
+    let config = gizmos.config_ext;
+    let (sphere_color, direction_color, frustum_color, in_range_color) = (
+        config.sphere_color,
+        config.direction_color,
+        config.frustum_color,
+        config.in_range_color,
+    );
+    let (directions, frusta, in_range) = (config.directions, config.frusta, config.in_range);
+
+    for (camera, gt, visible_entities) in cameras.iter() {
+        let center = gt.translation();
+        gizmos.sphere(
+            Isometry3d::from_translation(center),
+            camera.radius,
+            sphere_color,
+        );
+
+        if directions || frusta {
+            for y in 0..camera.grid_size {
+                for x in 0..camera.grid_size {
+                    let view = camera.tile_view_transform(center, UVec2::new(x, y));
+                    if directions {
+                        let start = view.translation();
+                        let end = start + view.forward() * camera.radius * 0.25;
+                        gizmos.arrow(start, end, direction_color);
+                    }
+                    if frusta {
+                        // the projection spans the sphere's diameter in each axis, from the camera to the far side
+                        let volume = view.mul_transform(
+                            Transform::from_xyz(0.0, 0.0, -camera.radius)
+                                .with_scale(Vec3::splat(camera.radius * 2.0)),
+                        );
+                        gizmos.cuboid(volume, frustum_color);
+                    }
+                }
+            }
+        }
+
+        if in_range {
+            // same bounds as the range check
+            for entity in visible_entities.iter::<With<Mesh3d>>() {
+                let Ok((transform, maybe_aabb)) = meshes.get(*entity) else {
+                    continue;
+                };
+                match maybe_aabb {
+                    Some(aabb) => {
+                        let center = transform.affine().transform_point3a(aabb.center);
+                        let radius = transform.radius_vec3a(aabb.half_extents);
+                        gizmos.sphere(
+                            Isometry3d::from_translation(Vec3::from(center)),
+                            radius,
+                            in_range_color,
+                        );
+                    }
+                    None => {
+                        gizmos.cross(
+                            Isometry3d::from_translation(transform.translation()),
+                            camera.radius * 0.05,
+                            in_range_color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::MeshAabb, primitives::Aabb, view::NoFrustumCulling},
+};
+
+use crate::{bake::BakeState, ImposterBakeCamera};
+
+/// fits an [`ImposterBakeCamera`]'s center and radius to the meshes under `root` (including `root` itself).
+/// add to the camera entity. the camera's transform and radius are overwritten until the bake starts, then
+/// the fit is frozen. changing (or re-inserting) the target fits it again.
+///
+/// the bake starts the frame after the bounds are first fitted, once every mesh under the root has bounds.
+/// meshes with `NoFrustumCulling` have no `Aabb`, so their bounds are computed from the mesh asset.
+/// `save_asset_callback` and `save_filterable_asset_callback` capture the radius, so create them on the
+/// [`ImposterBakeTargetFitted`] event rather than when spawning the camera.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ImposterBakeTarget {
+    pub root: Entity,
+    // added to the fitted radius, in world units
+    pub padding: f32,
+}
+
+impl ImposterBakeTarget {
+    pub fn new(root: Entity) -> Self {
+        Self { root, padding: 0.0 }
+    }
+
+    pub fn with_padding(mut self, padding: f32) -> Self {
+        self.padding = padding;
+        self
+    }
+}
+
+/// sent when an [`ImposterBakeTarget`] is first fitted, and when it changes its camera's center or radius
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ImposterBakeTargetFitted {
+    pub camera: Entity,
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+// marks cameras whose target bounds have been fitted, true once they are ready to bake
+#[derive(Component)]
+pub struct ImposterBakeTargetResolved(pub(crate) bool);
+
+/// an approximate minimal bounding sphere (ritter's algorithm), within ~5-20% of optimal.
+/// returns `None` for an empty set
+pub fn bounding_sphere(points: &[Vec3]) -> Option<(Vec3, f32)> {
+    let first = *points.first()?;
+    let farthest = |from: Vec3| {
+        points
+            .iter()
+            .copied()
+            .max_by(|a, b| {
+                a.distance_squared(from)
+                    .total_cmp(&b.distance_squared(from))
+            })
+            .unwrap()
+    };
+
+    // initial sphere across an approximate diameter
+    let a = farthest(first);
+    let b = farthest(a);
+    let mut center = (a + b) * 0.5;
+    let mut radius = a.distance(b) * 0.5;
+
+    // grow to enclose any outliers
+    for point in points {
+        let distance = point.distance(center);
+        if distance > radius {
+            let new_radius = (radius + distance) * 0.5;
+            center += (*point - center) * ((new_radius - radius) / distance);
+            radius = new_radius;
+        }
+    }
+
+    Some((center, radius))
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn fit_bake_targets(
+    mut commands: Commands,
+    mut fitted: EventWriter<ImposterBakeTargetFitted>,
+    mut cameras: Query<(
+        Entity,
+        Ref<ImposterBakeTarget>,
+        &mut ImposterBakeCamera,
+        &mut Transform,
+        &mut GlobalTransform,
+        Option<&Parent>,
+        Option<&mut ImposterBakeTargetResolved>,
+    )>,
+    children: Query<&Children>,
+    meshes: Query<
+        (
+            &Mesh3d,
+            &GlobalTransform,
+            Option<&Aabb>,
+            Has<NoFrustumCulling>,
+        ),
+        Without<ImposterBakeCamera>,
+    >,
+    parents: Query<&GlobalTransform, Without<ImposterBakeCamera>>,
+    mesh_assets: Res<Assets<Mesh>>,
+) {
+    let corners = [
+        Vec3::new(-1.0, -1.0, -1.0),
+        Vec3::new(-1.0, -1.0, 1.0),
+        Vec3::new(-1.0, 1.0, -1.0),
+        Vec3::new(-1.0, 1.0, 1.0),
+        Vec3::new(1.0, -1.0, -1.0),
+        Vec3::new(1.0, -1.0, 1.0),
+        Vec3::new(1.0, 1.0, -1.0),
+        Vec3::new(1.0, 1.0, 1.0),
+    ];
+
+    for (entity, target, mut camera, mut transform, mut gt, maybe_parent, resolved) in
+        cameras.iter_mut()
+    {
+        if camera.state != BakeState::Rendering {
+            continue;
+        }
+
+        // the bake has started, keep the fit it started with
+        if resolved.as_ref().is_some_and(|resolved| resolved.0) && !target.is_changed() {
+            continue;
+        }
+
+        // world space aabb corners. the sphere enclosing them encloses the boxes
+        let mut points = Vec::default();
+        let mut unbounded = false;
+        for mesh in std::iter::once(target.root).chain(children.iter_descendants(target.root)) {
+            let Ok((mesh_handle, mesh_transform, maybe_aabb, no_frustum_culling)) =
+                meshes.get(mesh)
+            else {
+                continue;
+            };
+            let aabb = match maybe_aabb {
+                Some(aabb) => *aabb,
+                // bevy doesn't calculate bounds for these
+                None if no_frustum_culling => {
+                    let Some(mesh_asset) = mesh_assets.get(mesh_handle) else {
+                        // not loaded yet
+                        unbounded = true;
+                        break;
+                    };
+                    let Some(aabb) = mesh_asset.compute_aabb() else {
+                        warn_once!(
+                            "imposter bake target mesh {mesh} has no positions, ignoring it"
+                        );
+                        continue;
+                    };
+                    aabb
+                }
+                None => {
+                    // bounds are not calculated yet
+                    unbounded = true;
+                    break;
+                }
+            };
+            let (center, half_extents) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+            points.extend(
+                corners
+                    .iter()
+                    .map(|c| mesh_transform.transform_point(center + half_extents * *c)),
+            );
+        }
+
+        if unbounded {
+            continue;
+        }
+        let Some((center, radius)) = bounding_sphere(&points) else {
+            continue;
+        };
+
+        let radius = radius + target.padding;
+        let mut changed = false;
+        if camera.radius != radius {
+            camera.radius = radius;
+            changed = true;
+        }
+
+        // we run after transform propagation, so update the global transform as well
+        let parent_gt = maybe_parent.and_then(|p| parents.get(p.get()).ok());
+        let translation = match parent_gt {
+            Some(parent_gt) => parent_gt.affine().inverse().transform_point3(center),
+            None => center,
+        };
+        if transform.translation != translation {
+            transform.translation = translation;
+            changed = true;
+            gt.set_if_neq(match parent_gt {
+                Some(parent_gt) => parent_gt.mul_transform(*transform),
+                None => GlobalTransform::from(*transform),
+            });
+        }
+
+        if changed || resolved.is_none() {
+            fitted.send(ImposterBakeTargetFitted {
+                camera: entity,
+                center,
+                radius,
+            });
+        }
+
+        // give a frame to respond to the event before baking
+        match resolved {
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(ImposterBakeTargetResolved(false));
+            }
+            Some(mut resolved) => {
+                // a changed target waits for a stable fit again
+                resolved.0 = !changed && !target.is_changed();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_encloses(points: &[Vec3], center: Vec3, radius: f32) {
+        for point in points {
+            assert!(
+                point.distance(center) <= radius + 1e-4,
+                "{point} outside {center} r {radius}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_has_no_sphere() {
+        assert_eq!(bounding_sphere(&[]), None);
+    }
+
+    #[test]
+    fn single_point() {
+        let point = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(bounding_sphere(&[point]), Some((point, 0.0)));
+    }
+
+    #[test]
+    fn box_corners() {
+        let center = Vec3::new(3.0, -1.0, 2.0);
+        let half_extents = Vec3::new(1.0, 2.0, 0.5);
+        let points = (0..8)
+            .map(|i| {
+                let sign = Vec3::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                );
+                center + half_extents * sign
+            })
+            .collect::<Vec<_>>();
+
+        let (fitted_center, radius) = bounding_sphere(&points).unwrap();
+        assert_encloses(&points, fitted_center, radius);
+        // opposite corners span the optimal sphere's diameter
+        assert!(fitted_center.distance(center) < 1e-4);
+        assert!((radius - half_extents.length()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn collinear_points() {
+        let points = [
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(-3.0, 1.0, 0.0),
+            Vec3::new(5.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ];
+
+        let (center, radius) = bounding_sphere(&points).unwrap();
+        assert_encloses(&points, center, radius);
+        assert!(center.distance(Vec3::new(1.0, 1.0, 0.0)) < 1e-4);
+        assert!((radius - 4.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,123 @@
+use async_channel::TryRecvError;
+use bevy::prelude::*;
+
+use crate::{GridMode, ImposterBakeCamera, ImposterBakeFailed, ImposterBakeFinished};
+
+/// the result of a bake started with [`bake_imposter`]
+#[derive(Clone, Debug)]
+pub struct BakedImposter {
+    /// packed material data (`Rg32Uint`), `grid_size * tile_size` pixels square
+    pub image: Image,
+    /// world space center of the captured sphere
+    pub center: Vec3,
+    pub radius: f32,
+    pub grid_size: u32,
+    pub tile_size: u32,
+    pub grid_mode: GridMode,
+}
+
+/// a bake in progress. poll it with [`BakeHandle::try_take`], or `.await` [`BakeHandle::wait`]
+pub struct BakeHandle {
+    camera: Entity,
+    receiver: async_channel::Receiver<Result<BakedImposter, String>>,
+}
+
+impl BakeHandle {
+    /// the bake camera. it is despawned once the bake finishes or fails
+    pub fn camera(&self) -> Entity {
+        self.camera
+    }
+
+    /// the result, if the bake has finished or failed
+    pub fn try_take(&self) -> Option<Result<BakedImposter, String>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Closed) => Some(Err(removed())),
+        }
+    }
+
+    /// waits for the bake to finish or fail
+    pub async fn wait(self) -> Result<BakedImposter, String> {
+        self.receiver
+            .recv()
+            .await
+            .unwrap_or_else(|_| Err(removed()))
+    }
+}
+
+fn removed() -> String {
+    "the bake camera was removed before the bake completed".to_owned()
+}
+
+// reports failures to the `BakeHandle`
+#[derive(Component)]
+pub(crate) struct ImposterBakeTask(async_channel::Sender<Result<BakedImposter, String>>);
+
+/// spawns a one-off bake of the area around `center`, replacing the camera's callback.
+/// the camera entity is despawned when the bake resolves.
+///
+/// ```ignore
+/// let handle = bake_imposter(&mut commands, ImposterBakeCamera { radius: 2.0, ..default() }, Vec3::ZERO);
+/// AsyncComputeTaskPool::get().spawn(async move {
+///     let baked = handle.wait().await?;
+///     // ...
+/// });
+/// ```
+pub fn bake_imposter(
+    commands: &mut Commands,
+    mut camera: ImposterBakeCamera,
+    center: Vec3,
+) -> BakeHandle {
+    let (sender, receiver) = async_channel::bounded(1);
+
+    camera.continuous = false;
+    let (radius, grid_size, tile_size, grid_mode) = (
+        camera.radius,
+        camera.grid_size,
+        camera.tile_size,
+        camera.grid_mode,
+    );
+    let callback_sender = sender.clone();
+    camera.set_callback(move |image| {
+        let _ = callback_sender.try_send(Ok(BakedImposter {
+            image,
+            center,
+            radius,
+            grid_size,
+            tile_size,
+            grid_mode,
+        }));
+    });
+
+    let camera = commands
+        .spawn((
+            camera,
+            Transform::from_translation(center),
+            ImposterBakeTask(sender),
+        ))
+        .id();
+
+    BakeHandle { camera, receiver }
+}
+
+pub(crate) fn resolve_bake_tasks(
+    mut commands: Commands,
+    mut finished: EventReader<ImposterBakeFinished>,
+    mut failed: EventReader<ImposterBakeFailed>,
+    tasks: Query<&ImposterBakeTask>,
+) {
+    // the result was already sent by the callback
+    for event in finished.read() {
+        if tasks.contains(event.camera) {
+            commands.entity(event.camera).despawn_recursive();
+        }
+    }
+
+    for event in failed.read() {
+        if let Ok(task) = tasks.get(event.camera) {
+            let _ = task.0.try_send(Err(event.reason.clone()));
+            commands.entity(event.camera).despawn_recursive();
+        }
+    }
+}
@@ -0,0 +1,467 @@
+// minimal block compression and ktx2 writing for the filterable storage.
+// quality is below dedicated encoders, but fast and dependency free.
+
+use bevy::math::{UVec2, Vec4};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Ktx2Format {
+    Rgba8Srgb,
+    Rgba8Unorm,
+    Rg8Unorm,
+    Bc7Srgb,
+    Bc7Unorm,
+    Bc5Unorm,
+}
+
+impl Ktx2Format {
+    fn vk_format(&self) -> u32 {
+        match self {
+            Ktx2Format::Rgba8Srgb => 43,
+            Ktx2Format::Rgba8Unorm => 37,
+            Ktx2Format::Rg8Unorm => 16,
+            Ktx2Format::Bc7Srgb => 146,
+            Ktx2Format::Bc7Unorm => 145,
+            Ktx2Format::Bc5Unorm => 141,
+        }
+    }
+
+    fn is_block_compressed(&self) -> bool {
+        matches!(
+            self,
+            Ktx2Format::Bc7Srgb | Ktx2Format::Bc7Unorm | Ktx2Format::Bc5Unorm
+        )
+    }
+
+    fn channels(&self) -> usize {
+        match self {
+            Ktx2Format::Rg8Unorm | Ktx2Format::Bc5Unorm => 2,
+            _ => 4,
+        }
+    }
+
+    fn is_srgb(&self) -> bool {
+        matches!(self, Ktx2Format::Rgba8Srgb | Ktx2Format::Bc7Srgb)
+    }
+
+    // khr data format descriptor: color model and (channel id, bit offset, bit length) per sample
+    fn dfd_samples(&self) -> (u8, Vec<(u8, u16, u8)>) {
+        match self {
+            Ktx2Format::Rgba8Srgb | Ktx2Format::Rgba8Unorm => (
+                1, // rgbsda
+                vec![(0, 0, 7), (1, 8, 7), (2, 16, 7), (15, 24, 7)],
+            ),
+            Ktx2Format::Rg8Unorm => (1, vec![(0, 0, 7), (1, 8, 7)]),
+            Ktx2Format::Bc7Srgb | Ktx2Format::Bc7Unorm => (134, vec![(0, 0, 127)]),
+            Ktx2Format::Bc5Unorm => (132, vec![(0, 0, 63), (1, 64, 63)]),
+        }
+    }
+}
+
+/// write a 2d texture with the given mip levels (largest first, tightly packed texels with `format.channels()` bytes each)
+pub fn write_ktx2(format: Ktx2Format, levels: &[(UVec2, Vec<u8>)]) -> Vec<u8> {
+    let encoded = levels
+        .iter()
+        .map(|(size, data)| match format {
+            Ktx2Format::Bc7Srgb | Ktx2Format::Bc7Unorm => {
+                encode_blocks(*size, data, 4, encode_bc7_block)
+            }
+            Ktx2Format::Bc5Unorm => encode_blocks(*size, data, 2, encode_bc5_block),
+            _ => data.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let (color_model, samples) = format.dfd_samples();
+    let block_size = 24 + 16 * samples.len();
+    let mut dfd = Vec::with_capacity(4 + block_size);
+    dfd.extend((4 + block_size as u32).to_le_bytes());
+    dfd.extend(0u32.to_le_bytes()); // vendor khronos, type basic
+    dfd.extend((2u32 | (block_size as u32) << 16).to_le_bytes()); // version 1.3
+    dfd.extend([
+        color_model,
+        1, // bt709 primaries
+        if format.is_srgb() { 2 } else { 1 },
+        0, // straight alpha
+    ]);
+    if format.is_block_compressed() {
+        dfd.extend([3, 3, 0, 0, 16, 0, 0, 0, 0, 0, 0, 0]);
+    } else {
+        dfd.extend([0, 0, 0, 0, format.channels() as u8, 0, 0, 0, 0, 0, 0, 0]);
+    }
+    for (channel, offset, length) in samples {
+        let channel = if format.is_srgb() && channel == 15 {
+            // linear alpha in an srgb texture
+            channel | 0x40
+        } else {
+            channel
+        };
+        dfd.extend(offset.to_le_bytes());
+        dfd.extend([length, channel]);
+        dfd.extend([0u8; 4]); // sample position
+        dfd.extend(0u32.to_le_bytes());
+        dfd.extend(if length == 7 { 255u32 } else { u32::MAX }.to_le_bytes());
+    }
+
+    const HEADER_SIZE: usize = 80;
+    let level_index_size = 24 * encoded.len();
+    let dfd_offset = HEADER_SIZE + level_index_size;
+    let data_start = (dfd_offset + dfd.len()).next_multiple_of(16);
+
+    // level data is stored smallest first
+    let mut level_offsets = vec![0usize; encoded.len()];
+    let mut offset = data_start;
+    for (ix, data) in encoded.iter().enumerate().rev() {
+        offset = offset.next_multiple_of(16);
+        level_offsets[ix] = offset;
+        offset += data.len();
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend([
+        0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+    ]);
+    let base = levels[0].0;
+    for v in [
+        format.vk_format(),
+        1, // type size
+        base.x,
+        base.y,
+        0, // depth
+        0, // layers
+        1, // faces
+        encoded.len() as u32,
+        0, // no supercompression
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0, // no key/values
+        0,
+    ] {
+        out.extend(v.to_le_bytes());
+    }
+    out.extend(0u64.to_le_bytes()); // no supercompression global data
+    out.extend(0u64.to_le_bytes());
+
+    for (data, offset) in encoded.iter().zip(level_offsets.iter()) {
+        out.extend((*offset as u64).to_le_bytes());
+        out.extend((data.len() as u64).to_le_bytes());
+        out.extend((data.len() as u64).to_le_bytes());
+    }
+    out.extend(dfd);
+
+    for (ix, data) in encoded.iter().enumerate().rev() {
+        out.resize(level_offsets[ix], 0);
+        out.extend(data);
+    }
+    out
+}
+
+// split into 4x4 blocks, repeating edge texels for partial blocks
+fn encode_blocks(
+    size: UVec2,
+    data: &[u8],
+    channels: usize,
+    encode: fn(&[[u8; 4]; 16]) -> [u8; 16],
+) -> Vec<u8> {
+    let blocks = (size + 3) / 4;
+    let mut out = Vec::with_capacity((blocks.x * blocks.y * 16) as usize);
+    for by in 0..blocks.y {
+        for bx in 0..blocks.x {
+            let mut texels = [[0u8; 4]; 16];
+            for (ix, texel) in texels.iter_mut().enumerate() {
+                let x = (bx * 4 + ix as u32 % 4).min(size.x - 1);
+                let y = (by * 4 + ix as u32 / 4).min(size.y - 1);
+                let start = (y * size.x + x) as usize * channels;
+                texel[..channels].copy_from_slice(&data[start..start + channels]);
+            }
+            out.extend(encode(&texels));
+        }
+    }
+    out
+}
+
+// bc7 mode 6: one subset, rgba endpoints with 7 bits + a p-bit, 4 bit indices
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn encode_bc7_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let pixels = texels.map(|t| Vec4::new(t[0] as f32, t[1] as f32, t[2] as f32, t[3] as f32));
+
+    // principal axis by power iteration
+    let mean = pixels.iter().copied().sum::<Vec4>() / 16.0;
+    let mut axis = pixels
+        .iter()
+        .map(|p| *p - mean)
+        .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+        .unwrap();
+    for _ in 0..8 {
+        let mut next = Vec4::ZERO;
+        for p in pixels.iter() {
+            let d = *p - mean;
+            next += d * d.dot(axis);
+        }
+        if next.length_squared() < 1e-6 {
+            break;
+        }
+        axis = next.normalize();
+    }
+    if axis.length_squared() < 1e-6 {
+        axis = Vec4::ONE.normalize();
+    } else {
+        axis = axis.normalize();
+    }
+
+    let (min_t, max_t) = pixels.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| {
+        let t = (*p - mean).dot(axis);
+        (lo.min(t), hi.max(t))
+    });
+    let ends = [
+        (mean + axis * min_t).clamp(Vec4::ZERO, Vec4::splat(255.0)),
+        (mean + axis * max_t).clamp(Vec4::ZERO, Vec4::splat(255.0)),
+    ];
+
+    // quantize to 7 bits + shared p-bit per endpoint
+    let quantized = ends.map(|e| {
+        (0..2u32)
+            .map(|p| {
+                let q = (e - p as f32) / 2.0;
+                let q = q.round().clamp(Vec4::ZERO, Vec4::splat(127.0));
+                let value = q * 2.0 + p as f32;
+                (q, p, (value - e).length_squared())
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap()
+    });
+    let mut endpoints = quantized.map(|(q, p, _)| (q.to_array().map(|v| v as u32), p));
+    let values = endpoints.map(|(q, p)| Vec4::from_array(q.map(|v| (v * 2 + p) as f32)));
+
+    let mut indices = pixels.map(|px| {
+        let mut best = (0, f32::MAX);
+        for (ix, w) in BC7_WEIGHTS.iter().enumerate() {
+            let w = *w as f32 / 64.0;
+            let err = (values[0] * (1.0 - w) + values[1] * w - px).length_squared();
+            if err < best.1 {
+                best = (ix as u32, err);
+            }
+        }
+        best.0
+    });
+
+    // the first index is stored with an implicit 0 msb
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut bits = BitWriter::default();
+    bits.write(1 << 6, 7);
+    for channel in 0..4 {
+        bits.write(endpoints[0].0[channel], 7);
+        bits.write(endpoints[1].0[channel], 7);
+    }
+    bits.write(endpoints[0].1, 1);
+    bits.write(endpoints[1].1, 1);
+    for (ix, index) in indices.iter().enumerate() {
+        bits.write(*index, if ix == 0 { 3 } else { 4 });
+    }
+    bits.0.to_le_bytes()
+}
+
+// two bc4 blocks, for red and green
+fn encode_bc5_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut out = [0u8; 16];
+    for channel in 0..2 {
+        let values = texels.map(|t| t[channel]);
+        let max = *values.iter().max().unwrap();
+        let min = *values.iter().min().unwrap();
+
+        // max > min selects the 8 value palette: index 0 -> max, 1 -> min, 2..7 interpolated from max to min
+        let mut bits = BitWriter::default();
+        bits.write(max as u32, 8);
+        bits.write(min as u32, 8);
+        for value in values {
+            let index = if max == min {
+                0
+            } else {
+                let step = ((max - value) as f32 * 7.0 / (max - min) as f32).round() as u32;
+                match step {
+                    0 => 0,
+                    7 => 1,
+                    s => s + 1,
+                }
+            };
+            bits.write(index, 3);
+        }
+        out[channel * 8..channel * 8 + 8].copy_from_slice(&bits.0.to_le_bytes()[0..8]);
+    }
+    out
+}
+
+#[derive(Default)]
+struct BitWriter(u128, u32);
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        self.0 |= ((value & ((1 << count) - 1)) as u128) << self.1;
+        self.1 += count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        image::{ktx2_buffer_to_image, CompressedImageFormats},
+        render::render_resource::TextureFormat,
+    };
+
+    use super::*;
+
+    struct BitReader(u128, u32);
+
+    impl BitReader {
+        fn read(&mut self, count: u32) -> u32 {
+            let value = (self.0 >> self.1) as u32 & ((1 << count) - 1);
+            self.1 += count;
+            value
+        }
+    }
+
+    fn decode_bc7_mode6(block: [u8; 16]) -> [[u8; 4]; 16] {
+        let mut bits = BitReader(u128::from_le_bytes(block), 0);
+        assert_eq!(bits.read(7), 1 << 6, "mode 6");
+        // both endpoints per channel
+        let channels: [[u32; 2]; 4] = std::array::from_fn(|_| [bits.read(7), bits.read(7)]);
+        let p = [bits.read(1), bits.read(1)];
+        let ends = [0, 1].map(|e| channels.map(|c| c[e] * 2 + p[e]));
+
+        std::array::from_fn(|ix| {
+            let w = BC7_WEIGHTS[bits.read(if ix == 0 { 3 } else { 4 }) as usize];
+            std::array::from_fn(|c| (((64 - w) * ends[0][c] + w * ends[1][c] + 32) >> 6) as u8)
+        })
+    }
+
+    fn decode_bc4(block: &[u8]) -> [u8; 16] {
+        let mut bits = BitReader(u64::from_le_bytes(block.try_into().unwrap()) as u128, 0);
+        let (e0, e1) = (bits.read(8), bits.read(8));
+        let palette: [u32; 8] = if e0 > e1 {
+            std::array::from_fn(|i| match i {
+                0 => e0,
+                1 => e1,
+                i => ((8 - i as u32) * e0 + (i as u32 - 1) * e1) / 7,
+            })
+        } else {
+            std::array::from_fn(|i| match i {
+                0 => e0,
+                1 => e1,
+                6 => 0,
+                7 => 255,
+                i => ((6 - i as u32) * e0 + (i as u32 - 1) * e1) / 5,
+            })
+        };
+        std::array::from_fn(|_| palette[bits.read(3) as usize] as u8)
+    }
+
+    fn max_error(a: &[[u8; 4]; 16], b: &[[u8; 4]; 16], channels: usize) -> u8 {
+        a.iter()
+            .zip(b.iter())
+            .flat_map(|(a, b)| (0..channels).map(move |c| a[c].abs_diff(b[c])))
+            .max()
+            .unwrap()
+    }
+
+    fn blocks() -> Vec<[[u8; 4]; 16]> {
+        vec![
+            // solid
+            [[200, 100, 50, 255]; 16],
+            // gradient along one axis
+            std::array::from_fn(|i| {
+                let v = (i * 17) as u8;
+                [v, 255 - v, v / 2, 255]
+            }),
+            // varying alpha
+            std::array::from_fn(|i| [40, 80, 120, (i * 16) as u8]),
+            // black and white
+            std::array::from_fn(|i| if i % 3 == 0 { [255; 4] } else { [0, 0, 0, 255] }),
+        ]
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        for texels in blocks() {
+            let decoded = decode_bc7_mode6(encode_bc7_block(&texels));
+            // colinear blocks only lose endpoint and index quantization
+            let error = max_error(&texels, &decoded, 4);
+            assert!(error <= 6, "{texels:?} -> {decoded:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn bc5_round_trip() {
+        for texels in blocks() {
+            let block = encode_bc5_block(&texels);
+            let red = decode_bc4(&block[0..8]);
+            let green = decode_bc4(&block[8..16]);
+            let decoded = std::array::from_fn(|i| [red[i], green[i], 0, 0]);
+            // 8 levels between the channel's extremes
+            let error = max_error(&texels, &decoded, 2);
+            assert!(error <= 255 / 14 + 1, "{texels:?} -> {decoded:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn partial_blocks_repeat_edges() {
+        // a 5x3 image is 2x1 blocks
+        let data = (0..15u8).flat_map(|i| [i * 10, i * 10]).collect::<Vec<_>>();
+        let encoded = encode_blocks(UVec2::new(5, 3), &data, 2, encode_bc5_block);
+        assert_eq!(encoded.len(), 2 * 16);
+        let red = decode_bc4(&encoded[16..24]);
+        // the second block's columns all repeat x = 4, rows past y = 2 repeat the last row
+        assert_eq!(red[0], 40);
+        assert_eq!(red[15], 140);
+    }
+
+    fn levels(channels: usize) -> Vec<(UVec2, Vec<u8>)> {
+        [UVec2::new(8, 4), UVec2::new(4, 2), UVec2::new(2, 1)]
+            .into_iter()
+            .map(|size| {
+                let data = (0..(size.x * size.y) as usize * channels)
+                    .map(|i| (i * 7) as u8)
+                    .collect();
+                (size, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ktx2_loads_in_bevy() {
+        for (format, texture_format, is_srgb) in [
+            (Ktx2Format::Rgba8Srgb, TextureFormat::Rgba8UnormSrgb, true),
+            (Ktx2Format::Rgba8Unorm, TextureFormat::Rgba8Unorm, false),
+            (Ktx2Format::Rg8Unorm, TextureFormat::Rg8Unorm, false),
+            (Ktx2Format::Bc7Srgb, TextureFormat::Bc7RgbaUnormSrgb, true),
+            (Ktx2Format::Bc7Unorm, TextureFormat::Bc7RgbaUnorm, false),
+            (Ktx2Format::Bc5Unorm, TextureFormat::Bc5RgUnorm, false),
+        ] {
+            let levels = levels(format.channels());
+            let bytes = write_ktx2(format, &levels);
+            let image = ktx2_buffer_to_image(&bytes, CompressedImageFormats::BC, is_srgb)
+                .unwrap_or_else(|e| panic!("{format:?}: {e}"));
+
+            assert_eq!(image.texture_descriptor.format, texture_format);
+            assert_eq!(image.texture_descriptor.size.width, 8);
+            assert_eq!(image.texture_descriptor.size.height, 4);
+            assert_eq!(image.texture_descriptor.mip_level_count, 3);
+
+            // levels are concatenated largest first, block compressed levels round up to whole blocks
+            let expected_len = levels
+                .iter()
+                .map(|(size, data)| match format.is_block_compressed() {
+                    true => (size.x.div_ceil(4) * size.y.div_ceil(4) * 16) as usize,
+                    false => data.len(),
+                })
+                .sum::<usize>();
+            assert_eq!(image.data.len(), expected_len, "{format:?}");
+            if !format.is_block_compressed() {
+                let expected = levels.iter().flat_map(|(_, data)| data.clone());
+                assert!(image.data.iter().copied().eq(expected), "{format:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use bevy::{
+    pbr::PreparedMaterial,
+    prelude::*,
+    render::{extract_resource::ExtractResource, render_asset::RenderAssets},
+};
+
+use crate::render::{ImposterKey, DEBUG_MODE_MASK, DEBUG_MODE_SHIFT};
+
+/// visualizes imposter internals in place of the lit material.
+///
+/// set per material with [`ImposterData::with_debug_mode`](crate::ImposterData::with_debug_mode), or insert as a
+/// resource to apply to every imposter. while the resource is anything but `None` it overrides the per-material
+/// modes, which apply again once it is back to `None`. atlas instances only use the resource. each mode is a
+/// separate pipeline, so switching recompiles.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ImposterDebugMode {
+    #[default]
+    None,
+    /// the highest weighted tile, red and green by grid position
+    TileIndices,
+    /// the weights of the blended tiles, in rgb
+    BlendWeights,
+    /// the baked depth, white at the front of the bounds
+    Depth,
+    /// the unpacked normal, in the imposter's local space
+    Normals,
+    /// red where the quad is clipped by the packed tile bounds, blue where it is inside but empty
+    PackedBounds,
+    /// blue for indexed, yellow for filterable and green for flat pixel storage
+    Storage,
+    /// magenta where pixels would be discarded against the depth prepass. needs a `DepthPrepass`, and
+    /// imposters without depth writes, as otherwise the hardware depth test hides them anyway
+    DepthDiscard,
+}
+
+impl ImposterDebugMode {
+    pub fn as_flags(&self) -> u32 {
+        let index = match self {
+            ImposterDebugMode::None => 0,
+            ImposterDebugMode::TileIndices => 1,
+            ImposterDebugMode::BlendWeights => 2,
+            ImposterDebugMode::Depth => 3,
+            ImposterDebugMode::Normals => 4,
+            ImposterDebugMode::PackedBounds => 5,
+            ImposterDebugMode::Storage => 6,
+            ImposterDebugMode::DepthDiscard => 7,
+        };
+        index << DEBUG_MODE_SHIFT
+    }
+
+    pub fn from_flags(flags: u32) -> Self {
+        match (flags & DEBUG_MODE_MASK) >> DEBUG_MODE_SHIFT {
+            0 => ImposterDebugMode::None,
+            1 => ImposterDebugMode::TileIndices,
+            2 => ImposterDebugMode::BlendWeights,
+            3 => ImposterDebugMode::Depth,
+            4 => ImposterDebugMode::Normals,
+            5 => ImposterDebugMode::PackedBounds,
+            6 => ImposterDebugMode::Storage,
+            7 => ImposterDebugMode::DepthDiscard,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn shader_def(&self) -> Option<&'static str> {
+        match self {
+            ImposterDebugMode::None => None,
+            ImposterDebugMode::TileIndices => Some("IMPOSTER_DEBUG_TILES"),
+            ImposterDebugMode::BlendWeights => Some("IMPOSTER_DEBUG_WEIGHTS"),
+            ImposterDebugMode::Depth => Some("IMPOSTER_DEBUG_DEPTH"),
+            ImposterDebugMode::Normals => Some("IMPOSTER_DEBUG_NORMALS"),
+            ImposterDebugMode::PackedBounds => Some("IMPOSTER_DEBUG_BOUNDS"),
+            ImposterDebugMode::Storage => Some("IMPOSTER_DEBUG_STORAGE"),
+            ImposterDebugMode::DepthDiscard => Some("IMPOSTER_DEBUG_DISCARD"),
+        }
+    }
+}
+
+/// material keys that carry the global [`ImposterDebugMode`]
+pub trait ImposterDebugKey {
+    fn set_debug_mode(&mut self, mode: ImposterDebugMode);
+}
+
+impl ImposterDebugKey for ImposterKey {
+    fn set_debug_mode(&mut self, mode: ImposterDebugMode) {
+        self.1 = mode;
+    }
+}
+
+impl<D> ImposterDebugKey for (ImposterKey, D) {
+    fn set_debug_mode(&mut self, mode: ImposterDebugMode) {
+        self.0.set_debug_mode(mode);
+    }
+}
+
+/// copies the global mode into the keys of prepared materials, so changing it respecializes their pipelines
+/// without modifying the assets
+pub(crate) fn apply_imposter_debug_mode<M: Material>(
+    mode: Res<ImposterDebugMode>,
+    mut materials: ResMut<RenderAssets<PreparedMaterial<M>>>,
+) where
+    M::Data: ImposterDebugKey,
+{
+    // materials are prepared without the global mode, so there is nothing to do while it stays `None`
+    if *mode == ImposterDebugMode::None && !mode.is_changed() {
+        return;
+    }
+
+    for (_, material) in materials.iter_mut() {
+        material.key.set_debug_mode(*mode);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{hash::Hash, marker::PhantomData};
+
+use bevy::{
+    ecs::system::SystemParamItem,
+    pbr::{
+        MaterialPipeline, MaterialPipelineKey, MeshPipelineKey, OpaqueRendererMethod,
+        PreparedMaterial,
+    },
+    prelude::*,
+    render::{
+        mesh::MeshVertexBufferLayoutRef,
+        render_asset::prepare_assets,
+        render_resource::{
+            AsBindGroup, AsBindGroupError, BindGroupLayout, BindGroupLayoutEntry,
+            RenderPipelineDescriptor, ShaderRef, SpecializedMeshPipelineError, UnpreparedBindGroup,
+        },
+        renderer::RenderDevice,
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    debug::apply_imposter_debug_mode,
+    render::{specialize_imposter, ImposterKey, FRAGMENT_HANDLE},
+    shadow_view::queue_imposter_shadows,
+    Imposter,
+};
+
+/// customizes imposter shading, like bevy's `MaterialExtension` does for standard materials.
+///
+/// the extension's bindings are appended to the imposter's material bind group (group 2), and should
+/// use indices from 100 up to stay clear of the imposter's own.
+///
+/// the fragment shader replaces the imposter's in every pass, and can use the `boimp::shading`
+/// functions to adjust the material before lighting:
+///
+/// ```wgsl
+/// #import boimp::shared::{ImposterVertexOut, ImposterFragmentOutput};
+/// #import boimp::shading::{sample_imposter, imposter_fragment_output};
+///
+/// @group(2) @binding(100) var<uniform> tint: vec4<f32>;
+///
+/// @fragment
+/// fn fragment(in: ImposterVertexOut) -> ImposterFragmentOutput {
+///     var sample = sample_imposter(in);
+///     sample.props.rgba = vec4(sample.props.rgba.rgb * tint.rgb, sample.props.rgba.a);
+///     return imposter_fragment_output(in, sample);
+/// }
+/// ```
+pub trait ImposterMaterialExtension: Asset + AsBindGroup + Clone + Sized {
+    fn fragment_shader() -> ShaderRef;
+
+    #[allow(unused_variables)]
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        key: ImposterExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        Ok(())
+    }
+}
+
+pub struct ImposterExtensionKey<E: ImposterMaterialExtension> {
+    pub mesh_key: MeshPipelineKey,
+    pub bind_group_data: E::Data,
+}
+
+/// an imposter with custom shading. register with an [`ImposterMaterialExtensionPlugin`].
+///
+/// `base` is a regular imposter, e.g. a clone of a loaded one.
+#[derive(Asset, TypePath, Clone, Debug)]
+pub struct ExtendedImposter<E: ImposterMaterialExtension> {
+    pub base: Imposter,
+    pub extension: E,
+}
+
+impl<E: ImposterMaterialExtension> AsBindGroup for ExtendedImposter<E> {
+    type Data = (ImposterKey, E::Data);
+    type Param = (<Imposter as AsBindGroup>::Param, E::Param);
+
+    fn unprepared_bind_group(
+        &self,
+        layout: &BindGroupLayout,
+        render_device: &RenderDevice,
+        (base_param, extension_param): &mut SystemParamItem<'_, '_, Self::Param>,
+    ) -> Result<UnpreparedBindGroup<Self::Data>, AsBindGroupError> {
+        let UnpreparedBindGroup {
+            mut bindings,
+            data: base_data,
+        } = self
+            .base
+            .unprepared_bind_group(layout, render_device, base_param)?;
+        let extension =
+            self.extension
+                .unprepared_bind_group(layout, render_device, extension_param)?;
+
+        bindings.extend(extension.bindings);
+
+        Ok(UnpreparedBindGroup {
+            bindings,
+            data: (base_data, extension.data),
+        })
+    }
+
+    fn bind_group_layout_entries(render_device: &RenderDevice) -> Vec<BindGroupLayoutEntry>
+    where
+        Self: Sized,
+    {
+        let mut entries = Imposter::bind_group_layout_entries(render_device);
+        entries.extend(E::bind_group_layout_entries(render_device));
+        entries
+    }
+}
+
+impl<E: ImposterMaterialExtension> Material for ExtendedImposter<E> {
+    fn vertex_shader() -> ShaderRef {
+        Imposter::vertex_shader()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        Imposter::prepass_vertex_shader()
+    }
+
+    fn deferred_vertex_shader() -> ShaderRef {
+        Imposter::deferred_vertex_shader()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        E::fragment_shader()
+    }
+
+    fn prepass_fragment_shader() -> ShaderRef {
+        E::fragment_shader()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        E::fragment_shader()
+    }
+
+    fn opaque_render_method(&self) -> OpaqueRendererMethod {
+        self.base.opaque_render_method()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.base.alpha_mode()
+    }
+
+    fn specialize(
+        pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // pipeline.fragment_shader is the extension's shader in every pass
+        let fragment_shader = pipeline.fragment_shader.clone().unwrap_or(FRAGMENT_HANDLE);
+        specialize_imposter(
+            descriptor,
+            key.mesh_key,
+            key.bind_group_data.0,
+            fragment_shader,
+        );
+
+        E::specialize(
+            descriptor,
+            layout,
+            ImposterExtensionKey {
+                mesh_key: key.mesh_key,
+                bind_group_data: key.bind_group_data.1,
+            },
+        )
+    }
+}
+
+/// registers an [`ExtendedImposter<E>`] material
+pub struct ImposterMaterialExtensionPlugin<E: ImposterMaterialExtension>(PhantomData<E>);
+
+impl<E: ImposterMaterialExtension> Default for ImposterMaterialExtensionPlugin<E> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<E: ImposterMaterialExtension> Plugin for ImposterMaterialExtensionPlugin<E>
+where
+    E::Data: PartialEq + Eq + Hash + Clone,
+{
+    fn build(&self, app: &mut App) {
+        // shadows are queued by `queue_imposter_shadows`
+        app.add_plugins(MaterialPlugin::<ExtendedImposter<E>> {
+            shadows_enabled: false,
+            ..default()
+        });
+
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_systems(
+                Render,
+                (
+                    apply_imposter_debug_mode::<ExtendedImposter<E>>
+                        .in_set(RenderSet::PrepareAssets)
+                        .after(prepare_assets::<PreparedMaterial<ExtendedImposter<E>>>),
+                    queue_imposter_shadows::<ExtendedImposter<E>>
+                        .in_set(RenderSet::QueueMeshes)
+                        .after(prepare_assets::<PreparedMaterial<ExtendedImposter<E>>>),
+                ),
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use bevy::{
+    image::{ImageSampler, ImageSamplerDescriptor},
+    math::UVec2,
+};
+
+use crate::{
+    compress::{write_ktx2, Ktx2Format},
+    material_props::{tile_mips, UnpackedMaterialProps},
+};
+
+// filterable storage: separate hardware-filterable textures instead of the bit-packed rg32uint props
+// albedo: rgba8 srgb (bc7)
+// normal: rg8 octahedral (bc5)
+// props: rgba8 depth, roughness, metallic, flags (bc7)
+
+pub const ALBEDO_FILE: &str = "albedo.ktx2";
+pub const NORMAL_FILE: &str = "normal.ktx2";
+pub const PROPS_FILE: &str = "props.ktx2";
+
+/// filterable texture files for a packed imposter image, as (file name, ktx2 bytes)
+pub fn filterable_ktx2_files(
+    size: UVec2,
+    packed: &[[u32; 2]],
+    compress: bool,
+) -> [(&'static str, Vec<u8>); 3] {
+    // pad to whole blocks, block compressed textures get rounded up on load otherwise
+    let padded_size = (size + 3) / 4 * 4;
+    let mut padded = vec![[0u32; 2]; (padded_size.x * padded_size.y) as usize];
+    for (row, source) in packed.chunks_exact(size.x as usize).enumerate() {
+        let start = row * padded_size.x as usize;
+        padded[start..start + size.x as usize].copy_from_slice(source);
+    }
+
+    // whole-texture mips to match hardware sampling, so tiles will bleed into each other at distance
+    let mut levels = vec![(padded_size, padded)];
+    levels.extend(
+        tile_mips(&levels[0].1, 1, padded_size)
+            .into_iter()
+            .map(|mip| (mip.tile_size, mip.data)),
+    );
+
+    let mut albedo = Vec::with_capacity(levels.len());
+    let mut normal = Vec::with_capacity(levels.len());
+    let mut props = Vec::with_capacity(levels.len());
+    for (size, data) in levels {
+        let mut level_albedo = Vec::with_capacity(data.len() * 4);
+        let mut level_normal = Vec::with_capacity(data.len() * 2);
+        let mut level_props = Vec::with_capacity(data.len() * 4);
+        for packed in data {
+            let unpacked = UnpackedMaterialProps::unpack(packed);
+            level_albedo.extend([
+                to_srgb_u8(unpacked.rgba.x),
+                to_srgb_u8(unpacked.rgba.y),
+                to_srgb_u8(unpacked.rgba.z),
+                to_u8(unpacked.rgba.w),
+            ]);
+            // reuse the packed octahedral coords
+            level_normal.extend(
+                [(packed[1] & 0xfff) >> 4, ((packed[1] >> 12) & 0xfff) >> 4].map(|v| v as u8),
+            );
+            level_props.extend([
+                to_u8(unpacked.depth),
+                to_u8(unpacked.roughness),
+                to_u8(unpacked.metallic),
+                (unpacked.flags * 17) as u8,
+            ]);
+        }
+        albedo.push((size, level_albedo));
+        normal.push((size, level_normal));
+        props.push((size, level_props));
+    }
+
+    let (rgba_srgb, rgba, rg) = if compress {
+        (
+            Ktx2Format::Bc7Srgb,
+            Ktx2Format::Bc7Unorm,
+            Ktx2Format::Bc5Unorm,
+        )
+    } else {
+        (
+            Ktx2Format::Rgba8Srgb,
+            Ktx2Format::Rgba8Unorm,
+            Ktx2Format::Rg8Unorm,
+        )
+    };
+
+    [
+        (ALBEDO_FILE, write_ktx2(rgba_srgb, &albedo)),
+        (NORMAL_FILE, write_ktx2(rg, &normal)),
+        (PROPS_FILE, write_ktx2(rgba, &props)),
+    ]
+}
+
+/// trilinear + anisotropic, used for all the filterable textures
+pub fn filterable_sampler() -> ImageSampler {
+    ImageSampler::Descriptor(ImageSamplerDescriptor {
+        anisotropy_clamp: 16,
+        ..ImageSamplerDescriptor::linear()
+    })
+}
+
+fn to_u8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+fn to_srgb_u8(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let srgb = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    to_u8(srgb)
+}
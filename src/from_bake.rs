@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+
+use crate::{
+    asset_loader::{
+        dummy_uint_image, flat_image, index_pixels, indexed_images, pack_asset, props_from_bytes,
+        settings_alpha_mode, settings_flags,
+    },
+    bake::ImposterBakeCamera,
+    bake_task::BakedImposter,
+    render::{IMPOSTER_WIND_BUFFER_HANDLE, INDEXED_FLAG},
+    GridMode, Imposter, ImposterData, ImposterLoaderSettings,
+};
+
+/// what [`Imposter::from_bake`] builds from
+pub enum BakeSource {
+    /// a finished bake, read back from the gpu
+    Baked(Box<BakedImposter>),
+    /// a bake camera's `target` image, e.g. for a continuous (dynamic) bake. the image stays on the gpu and is
+    /// used as is, so packing and indexing don't apply
+    Target {
+        image: Handle<Image>,
+        radius: f32,
+        grid_size: u32,
+        tile_size: u32,
+        grid_mode: GridMode,
+    },
+}
+
+impl From<BakedImposter> for BakeSource {
+    fn from(baked: BakedImposter) -> Self {
+        Self::Baked(Box::new(baked))
+    }
+}
+
+impl From<&ImposterBakeCamera> for BakeSource {
+    /// panics if the camera has no target, see [`ImposterBakeCamera::init_target`]
+    fn from(camera: &ImposterBakeCamera) -> Self {
+        Self::Target {
+            image: camera
+                .target
+                .clone()
+                .expect("bake camera has no target, call `init_target` first"),
+            radius: camera.radius,
+            grid_size: camera.grid_size,
+            tile_size: camera.tile_size,
+            grid_mode: camera.grid_mode,
+        }
+    }
+}
+
+/// builds an [`Imposter`] from a bake result without saving it, see [`Imposter::from_bake`]
+pub struct ImposterFromBake {
+    source: BakeSource,
+    pack: bool,
+    index: bool,
+    settings: ImposterLoaderSettings,
+}
+
+impl Imposter {
+    /// an in-memory imposter from a [`BakedImposter`], packed and indexed as `save_asset_callback` would.
+    /// spawn it at the bake's `center`. a bake camera with a target can be passed instead, for an imposter
+    /// that shows the target as it's baked (see [`BakeSource::Target`]).
+    ///
+    /// ```ignore
+    /// let imposter = Imposter::from_bake(baked)
+    ///     .with_settings(ImposterLoaderSettings { multisample: true, ..default() })
+    ///     .build(&mut images);
+    /// ```
+    pub fn from_bake(source: impl Into<BakeSource>) -> ImposterFromBake {
+        ImposterFromBake {
+            source: source.into(),
+            pack: true,
+            index: true,
+            settings: default(),
+        }
+    }
+}
+
+impl ImposterFromBake {
+    /// chop blank edges off the tiles
+    pub fn with_packing(mut self, pack: bool) -> Self {
+        self.pack = pack;
+        self
+    }
+
+    /// store unique pixels and per-pixel indices. only used if there is a size benefit
+    pub fn with_indexing(mut self, index: bool) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// render settings, as used when loading an asset
+    pub fn with_settings(mut self, settings: ImposterLoaderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn build(self, images: &mut Assets<Image>) -> Imposter {
+        let settings = self.settings;
+        let (image, radius, grid_size, tile_size, grid_mode) = match self.source {
+            BakeSource::Baked(baked) => {
+                let BakedImposter {
+                    image,
+                    radius,
+                    grid_size,
+                    tile_size,
+                    grid_mode,
+                    ..
+                } = *baked;
+                (image, radius, grid_size, tile_size, grid_mode)
+            }
+            BakeSource::Target {
+                image,
+                radius,
+                grid_size,
+                tile_size,
+                grid_mode,
+            } => {
+                let size = grid_size * tile_size;
+                return Imposter {
+                    data: ImposterData {
+                        center_and_scale: Vec3::ZERO.extend(radius),
+                        grid_size,
+                        flags: settings_flags(&settings, grid_mode),
+                        alpha: settings.alpha,
+                        parallax_steps: settings.parallax_steps,
+                        base_tile_size: tile_size,
+                        packed_tile_offset: UVec2::ZERO,
+                        packed_tile_size: UVec2::splat(tile_size),
+                    },
+                    pixels: image,
+                    indices: images.add(dummy_uint_image()),
+                    albedo_texture: None,
+                    normal_texture: None,
+                    props_texture: None,
+                    wind: IMPOSTER_WIND_BUFFER_HANDLE,
+                    alpha_mode: settings_alpha_mode(&settings),
+                    // rg32uint
+                    vram_bytes: (size * size * 8) as usize,
+                };
+            }
+        };
+
+        let (image, packed_tile_offset, packed_tile_size) = if self.pack {
+            pack_asset(grid_size as usize, &image)
+        } else {
+            (image, UVec2::ZERO, UVec2::splat(tile_size))
+        };
+
+        let indexed = if self.index {
+            index_pixels(&image.data)
+        } else {
+            None
+        };
+        let is_indexed = indexed.is_some();
+
+        let (pixels, indices, vram_bytes) = match indexed {
+            Some(indexed) => {
+                let (pixels, indices, vram_bytes) = indexed_images(
+                    props_from_bytes(indexed.palette.as_flattened()),
+                    &indexed.indices,
+                    grid_size,
+                    packed_tile_size,
+                    settings.keep_palette,
+                );
+                (images.add(pixels), images.add(indices), vram_bytes)
+            }
+            None => {
+                let (pixels, vram_bytes) = flat_image(image.data, grid_size, packed_tile_size);
+                (
+                    images.add(pixels),
+                    images.add(dummy_uint_image()),
+                    vram_bytes,
+                )
+            }
+        };
+
+        let flags =
+            settings_flags(&settings, grid_mode) + if is_indexed { INDEXED_FLAG } else { 0 };
+
+        Imposter {
+            data: ImposterData {
+                center_and_scale: Vec3::ZERO.extend(radius),
+                grid_size,
+                flags,
+                alpha: settings.alpha,
+                parallax_steps: settings.parallax_steps,
+                base_tile_size: tile_size,
+                packed_tile_offset,
+                packed_tile_size,
+            },
+            pixels,
+            indices,
+            albedo_texture: None,
+            normal_texture: None,
+            props_texture: None,
+            wind: IMPOSTER_WIND_BUFFER_HANDLE,
+            alpha_mode: settings_alpha_mode(&settings),
+            vram_bytes: vram_bytes as usize,
+        }
+    }
+}
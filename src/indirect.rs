@@ -0,0 +1,453 @@
+use std::{num::NonZeroU64, ops::Range};
+
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::core_3d::{
+        graph::{Core3d, Node3d},
+        Camera3d,
+    },
+    ecs::entity::EntityHashMap,
+    prelude::*,
+    render::{
+        primitives::Frustum,
+        render_asset::RenderAssets,
+        render_graph::{RenderGraphApp, RenderLabel, ViewNode, ViewNodeRunner},
+        render_resource::{
+            binding_types::{storage_buffer_read_only, storage_buffer_sized, uniform_buffer},
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, PipelineCache, StorageBuffer, UniformBuffer,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        storage::GpuShaderStorageBuffer,
+        sync_world::TemporaryRenderEntity,
+        view::ExtractedView,
+        Extract, Render, RenderApp, RenderSet,
+    },
+};
+use wgpu::ShaderStages;
+
+use crate::{
+    atlas::{
+        extract_imposter_atlas, prepare_imposter_atlas, prepare_imposter_atlas_bind_group,
+        ExtractedImposterAtlas, GpuImposterAtlas, ImposterAtlasBatch, ImposterAtlasEntry,
+        ImposterAtlasInstanceData, ImposterAtlasPipeline,
+    },
+    oct_coords::GRID_MASK,
+};
+
+pub const ATLAS_CULL_HANDLE: Handle<Shader> = Handle::weak_from_u128(381926750331718245);
+
+// vertex_count, instance_count, first_vertex, first_instance
+pub(crate) const DRAW_INDIRECT_ARGS_SIZE: u64 = 16;
+
+/// culls and draws large numbers of [`ImposterAtlas`](crate::ImposterAtlas) instances on the gpu, without
+/// per-entity extraction. instances are added to the [`ImposterIndirectInstances`] resource, and each 3d
+/// camera runs a compute pass to frustum and distance cull them before the prepass. shadow views draw them unculled.
+/// requires the [`ImposterAtlasPlugin`](crate::ImposterAtlasPlugin).
+pub struct ImposterIndirectPlugin;
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct ImposterCullLabel;
+
+impl Plugin for ImposterIndirectPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            ATLAS_CULL_HANDLE,
+            "shaders/atlas_cull.wgsl",
+            Shader::from_wgsl
+        );
+
+        app.init_resource::<ImposterIndirectInstances>();
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<GpuImposterIndirect>()
+            .add_systems(
+                ExtractSchedule,
+                extract_indirect_instances.after(extract_imposter_atlas),
+            )
+            .add_systems(
+                Render,
+                (
+                    prepare_indirect_instances
+                        .in_set(RenderSet::PrepareResources)
+                        .after(prepare_imposter_atlas),
+                    prepare_indirect_bind_groups
+                        .in_set(RenderSet::PrepareBindGroups)
+                        .after(prepare_imposter_atlas_bind_group),
+                ),
+            )
+            .add_render_graph_node::<ViewNodeRunner<ImposterCullNode>>(Core3d, ImposterCullLabel)
+            .add_render_graph_edges(
+                Core3d,
+                // the prepass draws the culled instances too
+                (ImposterCullLabel, Node3d::Prepass),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.init_resource::<ImposterCullPipeline>();
+    }
+}
+
+/// atlas instances that are culled and drawn on the gpu.
+/// any change re-uploads the full list, so this is best suited to large numbers of mostly static instances.
+#[derive(Resource)]
+pub struct ImposterIndirectInstances {
+    pub instances: Vec<ImposterAtlasInstanceData>,
+    // instances further than this from the camera are culled
+    pub max_distance: f32,
+}
+
+impl Default for ImposterIndirectInstances {
+    fn default() -> Self {
+        Self {
+            instances: Vec::default(),
+            max_distance: f32::INFINITY,
+        }
+    }
+}
+
+impl ImposterIndirectInstances {
+    /// add an instance of the atlas entry with the given index
+    pub fn add(&mut self, index: u32, transform: Transform) {
+        self.instances.push(ImposterAtlasInstanceData {
+            world_from_local: transform.compute_matrix(),
+            index,
+        });
+    }
+}
+
+// see `render::uniform`
+#[allow(dead_code)]
+mod uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Default)]
+    pub struct CullView {
+        pub(super) half_spaces: [Vec4; 6],
+        pub(super) world_position: Vec3,
+        pub(super) max_distance: f32,
+    }
+}
+pub use uniform::CullView;
+
+pub struct ImposterIndirectView {
+    cull_view: UniformBuffer<CullView>,
+    visible: Buffer,
+    pub(crate) draws: Buffer,
+    capacity: u64,
+    cull_bind_group: Option<BindGroup>,
+    pub(crate) visible_bind_group: Option<BindGroup>,
+}
+
+#[derive(Resource, Default)]
+pub struct GpuImposterIndirect {
+    instances: StorageBuffer<Vec<ImposterAtlasInstanceData>>,
+    count: u32,
+    // the instances of each grid mode, drawn unculled in shadow views
+    ranges: [Range<u32>; 3],
+    max_distance: f32,
+    dirty: bool,
+    pub(crate) views: EntityHashMap<ImposterIndirectView>,
+    pub(crate) atlas_bind_group: Option<BindGroup>,
+}
+
+pub fn extract_indirect_instances(
+    mut commands: Commands,
+    mut gpu_indirect: ResMut<GpuImposterIndirect>,
+    instances: Extract<Res<ImposterIndirectInstances>>,
+    atlas: Res<ExtractedImposterAtlas>,
+) {
+    gpu_indirect.max_distance = instances.max_distance;
+    if instances.is_changed() || atlas.is_changed() {
+        // sorted by grid mode, dropping instances of missing or removed entries
+        let mut sorted = instances
+            .instances
+            .iter()
+            .filter_map(|instance| {
+                let (data, ..) = atlas.entries.get(instance.index as usize)?.as_ref()?;
+                Some((data.flags & GRID_MASK, *instance))
+            })
+            .collect::<Vec<_>>();
+        sorted.sort_by_key(|(mode, _)| *mode);
+
+        gpu_indirect.ranges = std::array::from_fn(|mode| {
+            let start = sorted.partition_point(|(m, _)| (*m as usize) < mode) as u32;
+            let end = sorted.partition_point(|(m, _)| (*m as usize) <= mode) as u32;
+            start..end
+        });
+        gpu_indirect.count = sorted.len() as u32;
+        if sorted.is_empty() {
+            // an empty write keeps the old contents, drop the buffer so stale instances can't be culled
+            gpu_indirect.instances = StorageBuffer::default();
+        } else {
+            gpu_indirect
+                .instances
+                .set(sorted.into_iter().map(|(_, instance)| instance).collect());
+        }
+        gpu_indirect.dirty = true;
+    }
+
+    if gpu_indirect.count == 0 {
+        return;
+    }
+
+    // for each grid mode in use, a batch of the view's culled instances and an unculled batch for shadows
+    for (flags, range) in (0..).zip(gpu_indirect.ranges.clone()) {
+        if range.is_empty() {
+            continue;
+        }
+
+        commands.spawn((
+            ImposterAtlasBatch {
+                flags,
+                range: 0..0,
+                indirect: true,
+                culled: true,
+            },
+            TemporaryRenderEntity,
+        ));
+        commands.spawn((
+            ImposterAtlasBatch {
+                flags,
+                range,
+                indirect: true,
+                culled: false,
+            },
+            TemporaryRenderEntity,
+        ));
+    }
+}
+
+pub fn prepare_indirect_instances(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut gpu_indirect: ResMut<GpuImposterIndirect>,
+    views: Query<(Entity, &ExtractedView), With<Camera3d>>,
+) {
+    let gpu_indirect = &mut *gpu_indirect;
+    if gpu_indirect.dirty {
+        gpu_indirect.dirty = false;
+        gpu_indirect
+            .instances
+            .write_buffer(&render_device, &render_queue);
+    }
+
+    if gpu_indirect.count == 0 {
+        gpu_indirect.views.clear();
+        return;
+    }
+
+    gpu_indirect
+        .views
+        .retain(|entity, _| views.contains(*entity));
+
+    // one region of instance indices per grid mode
+    let capacity = gpu_indirect.count as u64 * 3;
+    let create_visible = || {
+        render_device.create_buffer(&BufferDescriptor {
+            label: Some("imposter_indirect_visible"),
+            size: capacity * 4,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    };
+
+    for (entity, view) in &views {
+        let indirect_view =
+            gpu_indirect
+                .views
+                .entry(entity)
+                .or_insert_with(|| ImposterIndirectView {
+                    cull_view: UniformBuffer::default(),
+                    visible: create_visible(),
+                    draws: render_device.create_buffer(&BufferDescriptor {
+                        label: Some("imposter_indirect_draws"),
+                        size: DRAW_INDIRECT_ARGS_SIZE * 3,
+                        usage: BufferUsages::STORAGE
+                            | BufferUsages::INDIRECT
+                            | BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    capacity,
+                    cull_bind_group: None,
+                    visible_bind_group: None,
+                });
+
+        if indirect_view.capacity != capacity {
+            indirect_view.visible = create_visible();
+            indirect_view.capacity = capacity;
+        }
+
+        let clip_from_world = view.clip_from_world.unwrap_or_else(|| {
+            view.clip_from_view * view.world_from_view.compute_matrix().inverse()
+        });
+        let frustum = Frustum::from_clip_from_world(&clip_from_world);
+        indirect_view.cull_view.set(CullView {
+            half_spaces: frustum.half_spaces.map(|half_space| half_space.normal_d()),
+            world_position: view.world_from_view.translation(),
+            max_distance: gpu_indirect.max_distance,
+        });
+        indirect_view
+            .cull_view
+            .write_buffer(&render_device, &render_queue);
+
+        // reset the instance counts, the cull pass fills them in
+        render_queue.write_buffer(
+            &indirect_view.draws,
+            0,
+            bytemuck::cast_slice(&[[6u32, 0, 0, 0]; 3]),
+        );
+    }
+}
+
+pub fn prepare_indirect_bind_groups(
+    render_device: Res<RenderDevice>,
+    atlas_pipeline: Res<ImposterAtlasPipeline>,
+    cull_pipeline: Res<ImposterCullPipeline>,
+    gpu_atlas: Res<GpuImposterAtlas>,
+    mut gpu_indirect: ResMut<GpuImposterIndirect>,
+    buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+) {
+    let gpu_indirect = &mut *gpu_indirect;
+    gpu_indirect.atlas_bind_group = None;
+    if gpu_indirect.count == 0 {
+        return;
+    }
+
+    let (Some(entries), Some(instances)) = (
+        gpu_atlas.entries.binding(),
+        gpu_indirect.instances.binding(),
+    ) else {
+        return;
+    };
+
+    gpu_indirect.atlas_bind_group = gpu_atlas.create_bind_group(
+        "imposter_indirect_atlas_bind_group",
+        &render_device,
+        &atlas_pipeline,
+        instances.clone(),
+        &buffers,
+    );
+    if gpu_indirect.atlas_bind_group.is_none() {
+        return;
+    }
+
+    for view in gpu_indirect.views.values_mut() {
+        let Some(cull_view) = view.cull_view.binding() else {
+            continue;
+        };
+
+        view.cull_bind_group = Some(render_device.create_bind_group(
+            "imposter_cull_bind_group",
+            &cull_pipeline.layout,
+            &BindGroupEntries::sequential((
+                cull_view,
+                entries.clone(),
+                instances.clone(),
+                view.visible.as_entire_binding(),
+                view.draws.as_entire_binding(),
+            )),
+        ));
+        view.visible_bind_group = Some(render_device.create_bind_group(
+            "imposter_visible_bind_group",
+            &atlas_pipeline.visible_layout,
+            &BindGroupEntries::single(view.visible.as_entire_binding()),
+        ));
+    }
+}
+
+#[derive(Resource)]
+pub struct ImposterCullPipeline {
+    pub layout: BindGroupLayout,
+    pub pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for ImposterCullPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let layout = render_device.create_bind_group_layout(
+            "imposter_cull_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    uniform_buffer::<CullView>(false),
+                    storage_buffer_read_only::<ImposterAtlasEntry>(false),
+                    storage_buffer_read_only::<ImposterAtlasInstanceData>(false),
+                    storage_buffer_sized(false, None),
+                    storage_buffer_sized(false, NonZeroU64::new(DRAW_INDIRECT_ARGS_SIZE * 3)),
+                ),
+            ),
+        );
+
+        let pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("imposter_cull_pipeline".into()),
+            layout: vec![layout.clone()],
+            push_constant_ranges: Vec::default(),
+            shader: ATLAS_CULL_HANDLE,
+            shader_defs: Vec::default(),
+            entry_point: "cull".into(),
+            zero_initialize_workgroup_memory: false,
+        });
+
+        Self { layout, pipeline }
+    }
+}
+
+#[derive(Default)]
+pub struct ImposterCullNode;
+
+impl ViewNode for ImposterCullNode {
+    type ViewQuery = ();
+
+    fn run<'w>(
+        &self,
+        graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut bevy::render::renderer::RenderContext<'w>,
+        _: bevy::ecs::query::QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        let gpu_indirect = world.resource::<GpuImposterIndirect>();
+        if gpu_indirect.count == 0 {
+            return Ok(());
+        }
+        let Some(bind_group) = gpu_indirect
+            .views
+            .get(&graph.view_entity())
+            .and_then(|view| view.cull_bind_group.as_ref())
+        else {
+            return Ok(());
+        };
+
+        let cull_pipeline = world.resource::<ImposterCullPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let Some(pipeline) = pipeline_cache.get_compute_pipeline(cull_pipeline.pipeline) else {
+            return Ok(());
+        };
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("imposter_cull"),
+                    timestamp_writes: None,
+                });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(gpu_indirect.count.div_ceil(64), 1, 1);
+
+        Ok(())
+    }
+}
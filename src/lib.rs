@@ -1,9 +1,41 @@
 pub mod asset_loader;
+pub mod atlas;
 pub mod bake;
+pub mod bake_gizmos;
+pub mod bake_target;
+pub mod bake_task;
+pub mod compress;
+pub mod debug;
+pub mod extension;
+pub mod filterable;
+pub mod from_bake;
+pub mod indirect;
+pub mod material_props;
 pub mod oct_coords;
+pub mod palette;
 pub mod render;
+pub mod shadow_proxy;
+pub mod shadow_view;
+pub mod wind;
 
 pub use asset_loader::ImposterLoaderSettings;
-pub use bake::{ImposterBakeCamera, ImposterBakePlugin};
+pub use atlas::{ImposterAtlas, ImposterAtlasInstance, ImposterAtlasPlugin};
+pub use bake::{
+    ImposterBakeCamera, ImposterBakeFailed, ImposterBakeFallback, ImposterBakeFinished,
+    ImposterBakePlugin, ImposterBakeProgress, ImposterBakeStarted, ImposterBakeUnsupported,
+    ImposterBakeWaiting,
+};
+pub use bake_gizmos::{ImposterBakeGizmoPlugin, ImposterBakeGizmos};
+pub use bake_target::{ImposterBakeTarget, ImposterBakeTargetFitted};
+pub use bake_task::{bake_imposter, BakeHandle, BakedImposter};
+pub use debug::ImposterDebugMode;
+pub use extension::{
+    ExtendedImposter, ImposterExtensionKey, ImposterMaterialExtension,
+    ImposterMaterialExtensionPlugin,
+};
+pub use from_bake::{BakeSource, ImposterFromBake};
+pub use indirect::{ImposterIndirectInstances, ImposterIndirectPlugin};
 pub use oct_coords::GridMode;
-pub use render::{Imposter, ImposterData, ImposterRenderPlugin};
+pub use render::{BillboardMode, Imposter, ImposterData, ImposterRenderPlugin, TileBlendMode};
+pub use shadow_proxy::ImposterShadowProxy;
+pub use wind::ImposterWind;
@@ -0,0 +1,329 @@
+use bevy::math::{UVec2, Vec2, Vec3, Vec4};
+
+// cpu side of the rg32uint material packing, must match shared.wgsl
+// r: [0-4] r, [5-9] g, [10-14] b, [15-19] a, [20-23] roughness, [24-27] metallic, [28-31] flags
+// g: [0-23] normal, [24-31] depth
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct UnpackedMaterialProps {
+    pub rgba: Vec4,
+    pub normal: Vec3,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub flags: u32,
+    // [0..1], unlike the shader which unpacks to [-1..1]
+    pub depth: f32,
+}
+
+fn pack_bits(input: f32, offset: u32, count: u32) -> u32 {
+    let mask = (1u32 << count) - 1;
+    ((input.clamp(0.0, 1.0) * mask as f32 + 0.5) as u32) << offset
+}
+
+fn unpack_bits(input: u32, offset: u32, count: u32) -> f32 {
+    let mask = (1u32 << count) - 1;
+    ((input >> offset) & mask) as f32 / mask as f32
+}
+
+fn spherical_uv_from_normal(dir: Vec3) -> Vec2 {
+    let octant = Vec3::new(sign(dir.x), sign(dir.y), sign(dir.z));
+    let octahedron = dir / dir.dot(octant);
+    let absolute = octahedron.abs();
+    let xz = if octahedron.y < 0.0 {
+        // fold by quadrant, counting 0 as positive so that straight down reaches a corner
+        let fold = Vec2::new(
+            if octahedron.x >= 0.0 { 1.0 } else { -1.0 },
+            if octahedron.z >= 0.0 { 1.0 } else { -1.0 },
+        );
+        fold * Vec2::new(1.0 - absolute.z, 1.0 - absolute.x)
+    } else {
+        Vec2::new(octahedron.x, octahedron.z)
+    };
+    (xz + 1.0) * 0.5
+}
+
+fn spherical_normal_from_uv(uv: Vec2) -> Vec3 {
+    let x = uv.x * 2.0 - 1.0;
+    let z = uv.y * 2.0 - 1.0;
+    let y = 1.0 - x.abs() - z.abs();
+
+    let n = if y < 0.0 {
+        Vec3::new(sign(x) * (1.0 - z.abs()), y, sign(z) * (1.0 - x.abs()))
+    } else {
+        Vec3::new(x, y, z)
+    };
+    n.normalize_or_zero()
+}
+
+// wgsl sign, 0 maps to 0
+fn sign(v: f32) -> f32 {
+    if v == 0.0 {
+        0.0
+    } else {
+        v.signum()
+    }
+}
+
+impl UnpackedMaterialProps {
+    pub fn unpack(packed: [u32; 2]) -> Self {
+        let [r, g] = packed;
+        Self {
+            rgba: Vec4::new(
+                unpack_bits(r, 0, 5),
+                unpack_bits(r, 5, 5),
+                unpack_bits(r, 10, 5),
+                unpack_bits(r, 15, 5),
+            ),
+            roughness: unpack_bits(r, 20, 4),
+            metallic: unpack_bits(r, 24, 4),
+            flags: r >> 28,
+            normal: spherical_normal_from_uv(Vec2::new(
+                unpack_bits(g, 0, 12),
+                unpack_bits(g, 12, 12),
+            )),
+            depth: unpack_bits(g, 24, 8),
+        }
+    }
+
+    pub fn pack(&self) -> [u32; 2] {
+        if self.rgba.w == 0.0 {
+            // keep empty pixels empty so packing and indexing still recognise them
+            return [0, 0];
+        }
+
+        let normal = spherical_uv_from_normal(self.normal);
+        [
+            pack_bits(self.rgba.x, 0, 5)
+                + pack_bits(self.rgba.y, 5, 5)
+                + pack_bits(self.rgba.z, 10, 5)
+                + pack_bits(self.rgba.w, 15, 5)
+                + pack_bits(self.roughness, 20, 4)
+                + pack_bits(self.metallic, 24, 4)
+                + (self.flags << 28),
+            pack_bits(normal.x, 0, 12) + pack_bits(normal.y, 12, 12) + pack_bits(self.depth, 24, 8),
+        ]
+    }
+
+    /// alpha-weighted average, the alpha itself is a plain average so that coverage is preserved
+    pub fn average(props: impl IntoIterator<Item = Self>) -> Self {
+        let mut out = Self::default();
+        let mut count = 0;
+        let mut total_weight = 0.0;
+        let mut max_weight = 0.0;
+
+        for p in props {
+            count += 1;
+            let w = p.rgba.w;
+            if w == 0.0 {
+                continue;
+            }
+
+            total_weight += w;
+            out.rgba += (p.rgba.truncate() * w).extend(w);
+            out.roughness += p.roughness * w;
+            out.metallic += p.metallic * w;
+            out.normal += p.normal * w;
+            out.depth += p.depth * w;
+            if w > max_weight {
+                max_weight = w;
+                out.flags = p.flags;
+            }
+        }
+
+        if total_weight == 0.0 {
+            return Self::default();
+        }
+
+        let rgb = out.rgba.truncate() / total_weight;
+        out.rgba = rgb.extend(out.rgba.w / count as f32);
+        out.roughness /= total_weight;
+        out.metallic /= total_weight;
+        out.normal = out.normal.normalize_or_zero();
+        out.depth /= total_weight;
+        out
+    }
+}
+
+/// a mip level of a grid of packed tiles
+pub struct TileMip {
+    pub tile_size: UVec2,
+    pub data: Vec<[u32; 2]>,
+}
+
+impl TileMip {
+    pub fn size(&self, grid_size: u32) -> UVec2 {
+        self.tile_size * grid_size
+    }
+}
+
+/// downsample each tile separately (so neighbouring tiles don't bleed into each other), halving the tile size
+/// (rounding down) per level until a tile is 1 pixel wide or high. the base level is not included.
+pub fn tile_mips(base: &[[u32; 2]], grid_size: u32, tile_size: UVec2) -> Vec<TileMip> {
+    let mut mips: Vec<TileMip> = Vec::default();
+
+    loop {
+        let (prev_tile_size, prev_data) = match mips.last() {
+            Some(prev) => (prev.tile_size, &prev.data[..]),
+            None => (tile_size, base),
+        };
+        let next_tile_size = prev_tile_size / 2;
+        if next_tile_size.min_element() == 0 {
+            return mips;
+        }
+
+        let prev_width = (prev_tile_size.x * grid_size) as usize;
+        let next_size = next_tile_size * grid_size;
+        let mut data = vec![[0u32; 2]; (next_size.x * next_size.y) as usize];
+
+        for y in 0..next_size.y {
+            for x in 0..next_size.x {
+                let tile = UVec2::new(x, y) / next_tile_size;
+                let local = UVec2::new(x, y) % next_tile_size;
+                // source range, covering odd leftovers in the last pixel
+                let start = local * prev_tile_size / next_tile_size;
+                let end = (local + 1) * prev_tile_size / next_tile_size;
+                let origin = tile * prev_tile_size;
+
+                let sources = (start.y..end.y).flat_map(|sy| {
+                    (start.x..end.x).map(move |sx| {
+                        let p = origin + UVec2::new(sx, sy);
+                        UnpackedMaterialProps::unpack(
+                            prev_data[p.y as usize * prev_width + p.x as usize],
+                        )
+                    })
+                });
+                data[(y * next_size.x + x) as usize] =
+                    UnpackedMaterialProps::average(sources).pack();
+            }
+        }
+
+        mips.push(TileMip {
+            tile_size: next_tile_size,
+            data,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(rgba: Vec4, normal: Vec3, depth: f32) -> UnpackedMaterialProps {
+        UnpackedMaterialProps {
+            rgba,
+            normal: normal.normalize(),
+            roughness: 0.4,
+            metallic: 0.8,
+            flags: 5,
+            depth,
+        }
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let samples = [
+            props(Vec4::new(1.0, 0.5, 0.0, 1.0), Vec3::Y, 0.0),
+            props(
+                Vec4::new(0.2, 0.9, 0.3, 0.5),
+                Vec3::new(1.0, -2.0, 0.5),
+                1.0,
+            ),
+            props(
+                Vec4::new(0.0, 0.0, 1.0, 1.0),
+                Vec3::new(-0.3, 0.1, -1.0),
+                0.37,
+            ),
+            props(Vec4::splat(0.7), Vec3::NEG_Y, 0.81),
+        ];
+
+        for p in samples {
+            let unpacked = UnpackedMaterialProps::unpack(p.pack());
+            // one quantization step of each field
+            assert!((unpacked.rgba - p.rgba).abs().max_element() <= 0.5 / 31.0 + 1e-6);
+            assert!((unpacked.roughness - p.roughness).abs() <= 0.5 / 15.0 + 1e-6);
+            assert!((unpacked.metallic - p.metallic).abs() <= 0.5 / 15.0 + 1e-6);
+            assert!((unpacked.depth - p.depth).abs() <= 0.5 / 255.0 + 1e-6);
+            assert_eq!(unpacked.flags, p.flags);
+            let angle = unpacked.normal.angle_between(p.normal);
+            assert!(
+                angle < 0.01,
+                "{:?} -> {:?}: {angle}",
+                p.normal,
+                unpacked.normal
+            );
+        }
+    }
+
+    #[test]
+    fn pack_is_stable() {
+        let packed = props(
+            Vec4::new(0.3, 0.6, 0.9, 1.0),
+            Vec3::new(0.2, 0.7, -0.4),
+            0.6,
+        )
+        .pack();
+        assert_eq!(UnpackedMaterialProps::unpack(packed).pack(), packed);
+    }
+
+    #[test]
+    fn empty_pixels_pack_to_zero() {
+        let empty = props(Vec4::new(1.0, 1.0, 1.0, 0.0), Vec3::X, 0.5);
+        assert_eq!(empty.pack(), [0, 0]);
+        assert_eq!(UnpackedMaterialProps::unpack([0, 0]).rgba.w, 0.0);
+    }
+
+    #[test]
+    fn average_ignores_empty_pixels() {
+        let red = props(Vec4::new(1.0, 0.0, 0.0, 1.0), Vec3::Y, 0.5);
+        let average = UnpackedMaterialProps::average([red, UnpackedMaterialProps::default()]);
+        assert_eq!(average.rgba.truncate(), red.rgba.truncate());
+        // coverage is averaged
+        assert_eq!(average.rgba.w, 0.5);
+    }
+
+    // a grid of tiles, each filled with one color
+    fn solid_tiles(grid_size: u32, tile_size: UVec2, colors: &[Vec4]) -> Vec<[u32; 2]> {
+        let size = tile_size * grid_size;
+        (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| UVec2::new(x, y) / tile_size))
+            .map(|tile| props(colors[(tile.y * grid_size + tile.x) as usize], Vec3::Y, 0.5).pack())
+            .collect()
+    }
+
+    #[test]
+    fn tile_mips_halve_each_tile() {
+        let colors = [
+            Vec4::new(1.0, 0.0, 0.0, 1.0),
+            Vec4::new(0.0, 1.0, 0.0, 1.0),
+            Vec4::new(0.0, 0.0, 1.0, 1.0),
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+        ];
+        let base = solid_tiles(2, UVec2::splat(8), &colors);
+        let mips = tile_mips(&base, 2, UVec2::splat(8));
+
+        let sizes = mips.iter().map(|m| m.tile_size).collect::<Vec<_>>();
+        assert_eq!(sizes, [UVec2::splat(4), UVec2::splat(2), UVec2::splat(1)]);
+
+        // tiles don't bleed into their neighbours
+        for mip in &mips {
+            let size = mip.size(2);
+            assert_eq!(mip.data.len(), (size.x * size.y) as usize);
+            for (i, packed) in mip.data.iter().enumerate() {
+                let tile = UVec2::new(i as u32 % size.x, i as u32 / size.x) / mip.tile_size;
+                let expected = colors[(tile.y * 2 + tile.x) as usize];
+                let rgba = UnpackedMaterialProps::unpack(*packed).rgba;
+                assert!((rgba - expected).abs().max_element() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn tile_mips_round_odd_sizes_down() {
+        // 5x3 tiles halve to 2x1, then stop before a zero height
+        let base = solid_tiles(3, UVec2::new(5, 3), &[Vec4::ONE; 9]);
+        let mips = tile_mips(&base, 3, UVec2::new(5, 3));
+        assert_eq!(mips.len(), 1);
+        assert_eq!(mips[0].tile_size, UVec2::new(2, 1));
+        assert_eq!(mips[0].data.len(), 6 * 3);
+    }
+}
@@ -0,0 +1,58 @@
+use bevy::prelude::*;
+
+use crate::{material_props::UnpackedMaterialProps, render::INDEXED_FLAG, Imposter};
+
+impl Imposter {
+    /// a variant of an indexed imposter that shares its `indices` image, with each palette entry passed through `recolor`.
+    /// the palette has to be kept in the main world, see `ImposterLoaderSettings::keep_palette`.
+    /// returns `None` for non-indexed imposters, or if the palette isn't available.
+    ///
+    /// ```ignore
+    /// let autumn = imposter.palette_variant(&mut images, |props| {
+    ///     let color = Hsla::from(LinearRgba::from_vec4(props.rgba)).rotate_hue(-40.0);
+    ///     props.rgba = LinearRgba::from(color).to_vec4();
+    /// });
+    /// ```
+    ///
+    /// the palette also holds the mip levels' averaged values, so recolors that don't commute with averaging
+    /// (like a remap table) may look slightly different at a distance.
+    pub fn palette_variant(
+        &self,
+        images: &mut Assets<Image>,
+        mut recolor: impl FnMut(&mut UnpackedMaterialProps),
+    ) -> Option<Imposter> {
+        if (self.data.flags & INDEXED_FLAG) == 0 {
+            return None;
+        }
+
+        let mut pixels = images.get(&self.pixels)?.clone();
+        if pixels.data.is_empty() {
+            return None;
+        }
+
+        for texel in pixels.data.chunks_exact_mut(8) {
+            let packed = [
+                u32::from_le_bytes(texel[0..4].try_into().unwrap()),
+                u32::from_le_bytes(texel[4..8].try_into().unwrap()),
+            ];
+            // empty and padding entries stay empty
+            if packed == [0, 0] {
+                continue;
+            }
+
+            let mut props = UnpackedMaterialProps::unpack(packed);
+            recolor(&mut props);
+            let [r, g] = props.pack();
+            texel[0..4].copy_from_slice(&r.to_le_bytes());
+            texel[4..8].copy_from_slice(&g.to_le_bytes());
+        }
+
+        let palette_bytes = pixels.data.len();
+        Some(Imposter {
+            pixels: images.add(pixels),
+            // the indices are shared, only count the new palette
+            vram_bytes: palette_bytes,
+            ..self.clone()
+        })
+    }
+}
@@ -1,25 +1,114 @@
 use bevy::{
     asset::load_internal_asset,
+    image::CompressedImageFormats,
+    pbr::{MeshPipelineKey, OpaqueRendererMethod, PreparedMaterial},
     prelude::*,
     render::{
-        render_asset::RenderAssetUsages,
-        render_resource::{AsBindGroup, ShaderRef, ShaderType},
+        extract_resource::ExtractResourcePlugin,
+        render_asset::{prepare_assets, RenderAssetUsages},
+        render_resource::{AsBindGroup, FragmentState, ShaderRef},
+        renderer::RenderDevice,
+        storage::ShaderStorageBuffer,
+        view::VisibilitySystems,
+        Render, RenderApp, RenderSet,
     },
 };
+use serde::{Deserialize, Serialize};
 use wgpu::{Extent3d, TextureFormat};
 
 use crate::{
     asset_loader::ImposterLoader,
+    debug::{apply_imposter_debug_mode, ImposterDebugMode},
     oct_coords::{GridMode, GRID_MASK},
+    shadow_proxy::{hide_shadow_proxies, update_shadow_proxies},
+    shadow_view::queue_imposter_shadows,
+    wind::{prepare_imposter_wind, wind_buffer, ImposterWind},
 };
 
 pub const BINDINGS_HANDLE: Handle<Shader> = Handle::weak_from_u128(659996873659996873);
 pub const FRAGMENT_HANDLE: Handle<Shader> = Handle::weak_from_u128(656126482580442360);
 pub const SHARED_HANDLE: Handle<Shader> = Handle::weak_from_u128(699899997614446892);
 pub const VERTEX_HANDLE: Handle<Shader> = Handle::weak_from_u128(591046068481766317);
+pub const SHADING_HANDLE: Handle<Shader> = Handle::weak_from_u128(614460218870913577);
+pub const IMPOSTER_DATA_HANDLE: Handle<Shader> = Handle::weak_from_u128(3587950799587133772);
+/// the shared wind buffer bound by every imposter, see [`Imposter::wind`]
+pub const IMPOSTER_WIND_BUFFER_HANDLE: Handle<ShaderStorageBuffer> =
+    Handle::weak_from_u128(2871040612373917146);
 
+// `ImposterData::flags` layout. bits 0-1: grid mode (`GRID_MASK`), 2-3: unused, 4-6: flags, 7: unused, 8: reserved for the
+// pipeline key (`SHADOW_VIEW_KEY`), 9-10: flags, 11-12: tile blend mode, 13-14: flags, 15-17: debug mode
 pub const RENDER_MULTISAMPLE_FLAG: u32 = 16;
 pub const INDEXED_FLAG: u32 = 32;
+pub const WRITE_DEPTH_FLAG: u32 = 64;
+// only set in pipeline keys, by `queue_imposter_shadows`. never stored in `ImposterData::flags`
+pub const SHADOW_VIEW_KEY: u32 = 256;
+pub const FILTERABLE_FLAG: u32 = 512;
+pub const VERTEX_TILES_FLAG: u32 = 1024;
+pub const WIND_FLAG: u32 = 8192;
+pub const AXIS_LOCKED_FLAG: u32 = 16384;
+pub const BLEND_MODE_SHIFT: u32 = 11;
+pub const BLEND_MODE_MASK: u32 = 3 << BLEND_MODE_SHIFT;
+pub const DEBUG_MODE_SHIFT: u32 = 15;
+pub const DEBUG_MODE_MASK: u32 = 7 << DEBUG_MODE_SHIFT;
+
+/// how many of the nearest tiles are sampled and blended per pixel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TileBlendMode {
+    /// the single closest tile. cheapest, but tiles pop as the view changes
+    Nearest,
+    /// the two closest tiles
+    TwoTile,
+    /// the three closest tiles (two in horizontal mode)
+    #[default]
+    ThreeTile,
+}
+
+impl TileBlendMode {
+    pub fn as_flags(&self) -> u32 {
+        match self {
+            TileBlendMode::ThreeTile => 0,
+            TileBlendMode::TwoTile => 1 << BLEND_MODE_SHIFT,
+            TileBlendMode::Nearest => 2 << BLEND_MODE_SHIFT,
+        }
+    }
+
+    pub fn from_flags(flags: u32) -> Self {
+        match (flags & BLEND_MODE_MASK) >> BLEND_MODE_SHIFT {
+            0 => TileBlendMode::ThreeTile,
+            1 => TileBlendMode::TwoTile,
+            2 => TileBlendMode::Nearest,
+            // unused, fall back to the default rather than panic while specializing
+            _ => TileBlendMode::ThreeTile,
+        }
+    }
+}
+
+/// how the imposter quad is oriented towards the camera
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BillboardMode {
+    /// face the camera fully
+    #[default]
+    Spherical,
+    /// only rotate around the object's up axis, so imposters don't lean when seen from above. tiles are selected
+    /// by yaw alone. best suited to `GridMode::Horizontal` assets. shadow and orthographic views face the camera.
+    AxisLocked,
+}
+
+impl BillboardMode {
+    pub fn as_flags(&self) -> u32 {
+        match self {
+            BillboardMode::Spherical => 0,
+            BillboardMode::AxisLocked => AXIS_LOCKED_FLAG,
+        }
+    }
+
+    pub fn from_flags(flags: u32) -> Self {
+        match flags & AXIS_LOCKED_FLAG {
+            0 => BillboardMode::Spherical,
+            _ => BillboardMode::AxisLocked,
+        }
+    }
+}
 
 pub struct ImposterRenderPlugin;
 
@@ -39,10 +128,62 @@ impl Plugin for ImposterRenderPlugin {
         );
         load_internal_asset!(app, SHARED_HANDLE, "shaders/shared.wgsl", Shader::from_wgsl);
         load_internal_asset!(app, VERTEX_HANDLE, "shaders/vertex.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            SHADING_HANDLE,
+            "shaders/shading.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            IMPOSTER_DATA_HANDLE,
+            "shaders/imposter_data.wgsl",
+            Shader::from_wgsl
+        );
+
+        // shadows are queued by `queue_imposter_shadows`
+        app.add_plugins(MaterialPlugin::<Imposter> {
+            shadows_enabled: false,
+            ..default()
+        })
+        .add_plugins(ExtractResourcePlugin::<ImposterWind>::default())
+        .add_plugins(ExtractResourcePlugin::<ImposterDebugMode>::default())
+        .preregister_asset_loader::<ImposterLoader>(&["boimp"])
+        .init_resource::<ImposterWind>()
+        .init_resource::<ImposterDebugMode>()
+        .add_systems(Startup, setup)
+        .add_systems(
+            PostUpdate,
+            (
+                update_shadow_proxies,
+                hide_shadow_proxies.after(VisibilitySystems::CheckVisibility),
+            ),
+        );
+
+        if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.add_systems(
+                Render,
+                (
+                    queue_imposter_shadows::<Imposter>
+                        .in_set(RenderSet::QueueMeshes)
+                        .after(prepare_assets::<PreparedMaterial<Imposter>>),
+                    apply_imposter_debug_mode::<Imposter>
+                        .in_set(RenderSet::PrepareAssets)
+                        .after(prepare_assets::<PreparedMaterial<Imposter>>),
+                    prepare_imposter_wind.in_set(RenderSet::PrepareResources),
+                ),
+            );
+        }
+    }
 
-        app.add_plugins(MaterialPlugin::<Imposter>::default())
-            .register_asset_loader(ImposterLoader)
-            .add_systems(Startup, setup);
+    fn finish(&self, app: &mut App) {
+        let supported_compressed_formats = match app.world().get_resource::<RenderDevice>() {
+            Some(render_device) => CompressedImageFormats::from_features(render_device.features()),
+            None => CompressedImageFormats::NONE,
+        };
+        app.register_asset_loader(ImposterLoader {
+            supported_compressed_formats,
+        });
     }
 }
 
@@ -50,7 +191,13 @@ impl Plugin for ImposterRenderPlugin {
 #[derive(Resource)]
 pub struct DummyIndicesImage(pub Handle<Image>);
 
-pub fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+pub fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    buffers.insert(&IMPOSTER_WIND_BUFFER_HANDLE, wind_buffer());
+
     let image = Image::new(
         Extent3d {
             width: 1,
@@ -65,16 +212,25 @@ pub fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
     commands.insert_resource(DummyIndicesImage(images.add(image)));
 }
 
-#[derive(ShaderType, Clone, Copy, PartialEq, Debug)]
-pub struct ImposterData {
-    pub center_and_scale: Vec4,
-    pub packed_tile_offset: UVec2,
-    pub packed_tile_size: UVec2,
-    pub grid_size: u32,
-    pub base_tile_size: u32,
-    pub flags: u32,
-    pub alpha: f32,
+// encase's `ShaderType` derive emits an unused `check` fn per field on recent toolchains. the lint can't be
+// allowed on the struct as the fns are generated beside it, so uniform structs live in small allowed modules
+#[allow(dead_code)]
+mod uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Clone, Copy, Default, PartialEq, Debug)]
+    pub struct ImposterData {
+        pub center_and_scale: Vec4,
+        pub packed_tile_offset: UVec2,
+        pub packed_tile_size: UVec2,
+        pub grid_size: u32,
+        pub base_tile_size: u32,
+        pub flags: u32,
+        pub alpha: f32,
+        pub parallax_steps: u32,
+    }
 }
+pub use uniform::ImposterData;
 
 impl ImposterData {
     #[allow(clippy::too_many_arguments)]
@@ -104,12 +260,68 @@ impl ImposterData {
                 }
                 + if indexed { INDEXED_FLAG } else { 0 },
             alpha,
+            parallax_steps: 0,
+        }
+    }
+
+    /// write per-pixel depth from the baked depth, so that imposters intersect other geometry and each other
+    /// correctly, in both the main pass and the prepass. this disables early depth testing so has some cost.
+    pub fn with_depth_writes(mut self, enabled: bool) -> Self {
+        self.flags &= !WRITE_DEPTH_FLAG;
+        if enabled {
+            self.flags |= WRITE_DEPTH_FLAG;
+        }
+        self
+    }
+
+    /// ray-march the baked depth over `steps` steps per tile rather than taking a single offset sample.
+    /// reduces swimming on thin or deep geometry at grazing angles. 0 or 1 disables.
+    pub fn with_parallax_steps(mut self, steps: u32) -> Self {
+        self.parallax_steps = steps;
+        self
+    }
+
+    /// how many tiles to blend per pixel
+    pub fn with_blend_mode(mut self, mode: TileBlendMode) -> Self {
+        self.flags = (self.flags & !BLEND_MODE_MASK) | mode.as_flags();
+        self
+    }
+
+    /// how the quad faces the camera
+    pub fn with_billboard_mode(mut self, mode: BillboardMode) -> Self {
+        self.flags = (self.flags & !AXIS_LOCKED_FLAG) | mode.as_flags();
+        self
+    }
+
+    /// sway with the [`ImposterWind`](crate::ImposterWind), more towards the top of the imposter
+    pub fn with_wind(mut self, enabled: bool) -> Self {
+        self.flags &= !WIND_FLAG;
+        if enabled {
+            self.flags |= WIND_FLAG;
         }
+        self
+    }
+
+    /// select tiles once per vertex from the direction to the imposter center, rather than per fragment.
+    /// cheaper for dense far-field imposters.
+    pub fn with_vertex_tiles(mut self, enabled: bool) -> Self {
+        self.flags &= !VERTEX_TILES_FLAG;
+        if enabled {
+            self.flags |= VERTEX_TILES_FLAG;
+        }
+        self
+    }
+
+    /// show a debug view instead of the lit material. overridden by the [`ImposterDebugMode`] resource unless it is `None`
+    pub fn with_debug_mode(mut self, mode: ImposterDebugMode) -> Self {
+        self.flags = (self.flags & !DEBUG_MODE_MASK) | mode.as_flags();
+        self
     }
 }
 
+// the material flags, and the global debug mode set by `apply_imposter_debug_mode`
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-pub struct ImposterKey(u32);
+pub struct ImposterKey(pub(crate) u32, pub(crate) ImposterDebugMode);
 
 #[derive(Asset, TypePath, AsBindGroup, Clone, Debug)]
 #[bind_group_data(ImposterKey)]
@@ -122,13 +334,24 @@ pub struct Imposter {
     // Res<DummyIndicesImage> gives a default you can drop in
     #[texture(2, dimension = "2d", sample_type = "u_int")]
     pub indices: Handle<Image>,
+    // filterable storage, used in place of `pixels` when `FILTERABLE_FLAG` is set
+    #[texture(3)]
+    #[sampler(6)]
+    pub albedo_texture: Option<Handle<Image>>,
+    #[texture(4)]
+    pub normal_texture: Option<Handle<Image>>,
+    #[texture(5)]
+    pub props_texture: Option<Handle<Image>>,
+    /// the global wind, only read with wind enabled. use [`IMPOSTER_WIND_BUFFER_HANDLE`]
+    #[storage(7, read_only)]
+    pub wind: Handle<ShaderStorageBuffer>,
     pub alpha_mode: AlphaMode,
     pub vram_bytes: usize,
 }
 
 impl From<&Imposter> for ImposterKey {
     fn from(value: &Imposter) -> Self {
-        Self(value.data.flags)
+        Self(value.data.flags & !SHADOW_VIEW_KEY, ImposterDebugMode::None)
     }
 }
 
@@ -149,6 +372,19 @@ impl Material for Imposter {
         FRAGMENT_HANDLE.into()
     }
 
+    fn deferred_vertex_shader() -> ShaderRef {
+        VERTEX_HANDLE.into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        FRAGMENT_HANDLE.into()
+    }
+
+    // follow the app's `DefaultOpaqueRendererMethod`
+    fn opaque_render_method(&self) -> OpaqueRendererMethod {
+        OpaqueRendererMethod::Auto
+    }
+
     fn alpha_mode(&self) -> AlphaMode {
         self.alpha_mode
     }
@@ -159,26 +395,120 @@ impl Material for Imposter {
         _: &bevy::render::mesh::MeshVertexBufferLayoutRef,
         key: bevy::pbr::MaterialPipelineKey<Self>,
     ) -> Result<(), bevy::render::render_resource::SpecializedMeshPipelineError> {
-        let vert_defs = &mut descriptor.vertex.shader_defs;
-        let frag_defs = &mut descriptor.fragment.as_mut().unwrap().shader_defs;
+        specialize_imposter(
+            descriptor,
+            key.mesh_key,
+            key.bind_group_data,
+            FRAGMENT_HANDLE,
+        );
+        Ok(())
+    }
+}
 
-        if (key.bind_group_data.0 & RENDER_MULTISAMPLE_FLAG) != 0 {
-            frag_defs.push("MATERIAL_MULTISAMPLE".into());
-        }
-        let grid_mode = match key.bind_group_data.0 & GRID_MASK {
-            i if i == GridMode::Hemispherical.as_flags() => "GRID_HEMISPHERICAL",
-            i if i == GridMode::Spherical.as_flags() => "GRID_SPHERICAL",
-            i if i == GridMode::Horizontal.as_flags() => "GRID_HORIZONTAL",
-            _ => panic!(),
-        };
-        vert_defs.push(grid_mode.into());
-        frag_defs.push(grid_mode.into());
+// shared with `ExtendedImposter`. `fragment_shader` is used when a depth-only pass needs a fragment stage added
+pub(crate) fn specialize_imposter(
+    descriptor: &mut bevy::render::render_resource::RenderPipelineDescriptor,
+    mesh_key: MeshPipelineKey,
+    key: ImposterKey,
+    fragment_shader: Handle<Shader>,
+) {
+    let ImposterKey(flags, global_debug_mode) = key;
 
-        if (key.bind_group_data.0 & INDEXED_FLAG) != 0 {
-            // indexed
-            frag_defs.push("INDEXED_PIXELS".into());
-        }
+    // point and spot light views are marked in the material key, directional views clamp depth
+    let is_shadow =
+        (flags & SHADOW_VIEW_KEY) != 0 || mesh_key.contains(MeshPipelineKey::DEPTH_CLAMP_ORTHO);
 
-        Ok(())
+    // shadow casters always write the baked depth so that shadows have volume, and the gbuffer needs it so
+    // that deferred lighting sees the baked surface rather than the quad
+    let deferred = mesh_key.contains(MeshPipelineKey::DEFERRED_PREPASS);
+    if (flags & WRITE_DEPTH_FLAG) != 0 || is_shadow || deferred {
+        // the depth-only prepass may not have a fragment stage
+        let fragment = descriptor.fragment.get_or_insert_with(|| FragmentState {
+            shader: fragment_shader,
+            shader_defs: descriptor.vertex.shader_defs.clone(),
+            entry_point: "fragment".into(),
+            targets: Vec::default(),
+        });
+        fragment.shader_defs.push("WRITE_DEPTH".into());
+        descriptor.vertex.shader_defs.push("WRITE_DEPTH".into());
+    }
+
+    let vert_defs = &mut descriptor.vertex.shader_defs;
+    let frag_defs = &mut descriptor.fragment.as_mut().unwrap().shader_defs;
+
+    if (flags & FILTERABLE_FLAG) != 0 {
+        // hardware filtered, so no need to multisample
+        frag_defs.push("FILTERABLE_PIXELS".into());
+    } else if (flags & RENDER_MULTISAMPLE_FLAG) != 0 {
+        frag_defs.push("MATERIAL_MULTISAMPLE".into());
+    }
+    let grid_mode = grid_mode_shader_def(flags);
+    vert_defs.push(grid_mode.into());
+    frag_defs.push(grid_mode.into());
+
+    if (flags & INDEXED_FLAG) != 0 {
+        // indexed
+        frag_defs.push("INDEXED_PIXELS".into());
+    }
+
+    match TileBlendMode::from_flags(flags) {
+        TileBlendMode::Nearest => frag_defs.push("TILE_BLEND_NEAREST".into()),
+        TileBlendMode::TwoTile => frag_defs.push("TILE_BLEND_TWO".into()),
+        TileBlendMode::ThreeTile => (),
+    }
+
+    // a quad locked to the up axis is degenerate for lights looking down it, so shadows keep facing the light
+    if BillboardMode::from_flags(flags) == BillboardMode::AxisLocked && !is_shadow {
+        vert_defs.push("AXIS_LOCKED".into());
+        frag_defs.push("AXIS_LOCKED".into());
+    }
+
+    if (flags & WIND_FLAG) != 0 {
+        vert_defs.push("IMPOSTER_WIND".into());
+        frag_defs.push("IMPOSTER_WIND".into());
+    }
+
+    if (flags & VERTEX_TILES_FLAG) != 0 {
+        vert_defs.push("VERTEX_TILES".into());
+        frag_defs.push("VERTEX_TILES".into());
+    }
+
+    let debug_mode = match global_debug_mode {
+        ImposterDebugMode::None => ImposterDebugMode::from_flags(flags),
+        global => global,
+    };
+    if let Some(def) = debug_mode.shader_def() {
+        frag_defs.push(def.into());
+    }
+}
+
+pub(crate) fn grid_mode_shader_def(flags: u32) -> &'static str {
+    match flags & GRID_MASK {
+        i if i == GridMode::Hemispherical.as_flags() => "GRID_HEMISPHERICAL",
+        i if i == GridMode::Spherical.as_flags() => "GRID_SPHERICAL",
+        i if i == GridMode::Horizontal.as_flags() => "GRID_HORIZONTAL",
+        _ => panic!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_mode_flags_round_trip() {
+        for mode in [
+            TileBlendMode::Nearest,
+            TileBlendMode::TwoTile,
+            TileBlendMode::ThreeTile,
+        ] {
+            let flags = mode.as_flags() | WIND_FLAG | AXIS_LOCKED_FLAG;
+            assert_eq!(TileBlendMode::from_flags(flags), mode);
+        }
+        // the unused value doesn't panic
+        assert_eq!(
+            TileBlendMode::from_flags(BLEND_MODE_MASK),
+            TileBlendMode::ThreeTile
+        );
     }
 }
@@ -0,0 +1,83 @@
+#import boimp::imposter_data::ImposterData
+
+struct ImposterAtlasEntry {
+    data: ImposterData,
+    pixels_size: vec2<u32>,
+}
+
+struct ImposterAtlasInstance {
+    world_from_local: mat4x4<f32>,
+    index: u32,
+}
+
+struct CullView {
+    half_spaces: array<vec4<f32>, 6>,
+    world_position: vec3<f32>,
+    max_distance: f32,
+}
+
+struct DrawIndirectArgs {
+    vertex_count: u32,
+    instance_count: atomic<u32>,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+// must match GRID_MASK in oct_coords.rs
+const GRID_MASK: u32 = 3;
+
+@group(0) @binding(0)
+var<uniform> cull_view: CullView;
+
+@group(0) @binding(1)
+var<storage> entries: array<ImposterAtlasEntry>;
+
+@group(0) @binding(2)
+var<storage> instances: array<ImposterAtlasInstance>;
+
+// one region of `arrayLength(&instances)` per grid mode
+@group(0) @binding(3)
+var<storage, read_write> visible: array<u32>;
+
+// one draw per grid mode
+@group(0) @binding(4)
+var<storage, read_write> draws: array<DrawIndirectArgs, 3>;
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let instance_count = arrayLength(&instances);
+    if id.x >= instance_count {
+        return;
+    }
+
+    let instance = instances[id.x];
+    // an index past the atlas entries
+    if instance.index >= arrayLength(&entries) {
+        return;
+    }
+    let entry = entries[instance.index];
+    // the slot of a removed entry
+    if entry.pixels_size.x == 0u {
+        return;
+    }
+    let data = entry.data;
+    let model = instance.world_from_local;
+
+    let center = (model * vec4<f32>(data.center_and_scale.xyz, 1.0)).xyz;
+    let max_scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = data.center_and_scale.w * max_scale;
+
+    if distance(center, cull_view.world_position) - radius > cull_view.max_distance {
+        return;
+    }
+
+    for (var i = 0u; i < 6u; i++) {
+        if dot(cull_view.half_spaces[i], vec4<f32>(center, 1.0)) + radius <= 0.0 {
+            return;
+        }
+    }
+
+    let mode = data.flags & GRID_MASK;
+    let slot = atomicAdd(&draws[mode].instance_count, 1u);
+    visible[mode * instance_count + slot] = id.x;
+}
@@ -1,18 +1,63 @@
 #define_import_path boimp::bindings
 
-#import bevy_pbr::{
-    view_transformations::position_view_to_world,
-}
+// part of the shader api for custom imposter shading: `imposter_data` and the sampling functions are kept stable
 
+#import boimp::imposter_data::ImposterData
 #import boimp::shared::{
-    ImposterData, 
     UnpackedMaterialProps,
+    IMPOSTER_INDEXED_FLAG,
     spherical_normal_from_uv,
     spherical_uv_from_normal, 
     unpack_props,
     weighted_props,
+    view_is_orthographic,
 };
 
+#ifdef IMPOSTER_ATLAS
+struct ImposterAtlasEntry {
+    data: ImposterData,
+    pixels_size: vec2<u32>,
+}
+
+struct ImposterAtlasInstance {
+    world_from_local: mat4x4<f32>,
+    index: u32,
+}
+
+@group(2) @binding(0)
+var imposter_atlas_pixels: texture_2d_array<u32>;
+
+@group(2) @binding(1)
+var imposter_atlas_indices: texture_2d_array<u32>;
+
+@group(2) @binding(2)
+var<storage> imposter_atlas_entries: array<ImposterAtlasEntry>;
+
+@group(2) @binding(3)
+var<storage> imposter_atlas_instances: array<ImposterAtlasInstance>;
+
+// xyz: direction * strength, w: sway phase in radians. shared by all imposters, see `ImposterWind`
+@group(2) @binding(4)
+var<storage> imposter_wind: vec4<f32>;
+
+#ifdef IMPOSTER_ATLAS_INDIRECT
+// culled instance indices, one region per grid mode
+@group(1) @binding(0)
+var<storage> imposter_atlas_visible: array<u32>;
+#endif
+
+// the entry used by the current instance, must be initialized with `set_atlas_entry`
+var<private> imposter_data: ImposterData;
+var<private> imposter_atlas_layer: u32;
+var<private> imposter_atlas_pixels_size: vec2<u32>;
+
+fn set_atlas_entry(index: u32) {
+    let entry = imposter_atlas_entries[index];
+    imposter_data = entry.data;
+    imposter_atlas_layer = index;
+    imposter_atlas_pixels_size = entry.pixels_size;
+}
+#else
 @group(2) @binding(0)
 var<uniform> imposter_data: ImposterData;
 
@@ -24,6 +69,40 @@ var imposter_pixels: texture_2d<u32>;
 var imposter_indices: texture_2d<u32>;
 #endif
 
+#ifdef FILTERABLE_PIXELS
+@group(2) @binding(3)
+var imposter_albedo: texture_2d<f32>;
+@group(2) @binding(4)
+var imposter_normal: texture_2d<f32>;
+@group(2) @binding(5)
+var imposter_props: texture_2d<f32>;
+@group(2) @binding(6)
+var imposter_sampler: sampler;
+
+// screen-space gradients of the current tile's pixel coords, set in `sample_tile_material`.
+// explicit gradients let us sample inside the (non-uniform) depth march
+var<private> imposter_pixel_gradients: vec4<f32>;
+#endif
+
+#ifdef IMPOSTER_WIND
+// xyz: direction * strength, w: sway phase in radians. shared by all imposters, see `ImposterWind`
+@group(2) @binding(7)
+var<storage> imposter_wind: vec4<f32>;
+#endif
+#endif
+
+// screen-space derivatives of the quad's local position, see `set_position_gradients`
+var<private> imposter_position_dx: vec3<f32>;
+var<private> imposter_position_dy: vec3<f32>;
+
+// take the derivatives used for mip selection (and filtering) from the quad's local position. call in
+// uniform control flow before sampling: the per-tile uvs jump where the selected tiles change, and
+// derivatives after a discard are undefined
+fn set_position_gradients(local_position: vec3<f32>) {
+    imposter_position_dx = dpdx(local_position);
+    imposter_position_dy = dpdy(local_position);
+}
+
 struct SamplePositions {
     tile_indices: array<vec2<u32>, 3>,
     tile_weights: vec3<f32>,
@@ -53,6 +132,18 @@ fn oct_sample_positions(uv: vec2<f32>) -> SamplePositions {
     return sample_positions;
 }
 
+// the tile direction for a (normalized) local camera direction. axis-locked quads stay upright, so their tiles
+// are selected by the projected yaw alone, and the perspective ray through each tile's plane in
+// `sample_uvs_unbounded` offsets the parallax for slight elevation. matches the quad facing in vertex.wgsl
+fn billboard_tile_dir(dir: vec3<f32>) -> vec3<f32> {
+#ifdef AXIS_LOCKED
+    if abs(dir.y) < 0.99 && !view_is_orthographic() {
+        return normalize(vec3(dir.x, 0.0, dir.z));
+    }
+#endif
+    return dir;
+}
+
 fn sample_positions_from_camera_dir(dir: vec3<f32>) -> SamplePositions {
     let grid_size = f32(imposter_data.grid_size);
 
@@ -87,12 +178,30 @@ fn sample_positions_from_camera_dir(dir: vec3<f32>) -> SamplePositions {
 #endif
 }
 
+// order by descending weight, so that the closest tiles come first
+fn sort_samples_by_weight(samples: SamplePositions) -> SamplePositions {
+    var sorted = samples;
+    for (var i = 0u; i < 2u; i++) {
+        for (var j = 0u; j < 2u - i; j++) {
+            if sorted.tile_weights[j] < sorted.tile_weights[j + 1u] {
+                let index = sorted.tile_indices[j];
+                let weight = sorted.tile_weights[j];
+                sorted.tile_indices[j] = sorted.tile_indices[j + 1u];
+                sorted.tile_weights[j] = sorted.tile_weights[j + 1u];
+                sorted.tile_indices[j + 1u] = index;
+                sorted.tile_weights[j + 1u] = weight;
+            }
+        }
+    }
+    return sorted;
+}
+
 struct Basis {
     normal: vec3<f32>,
     up: vec3<f32>,
 }
 
-fn oct_mode_normal_from_uv(grid_index: vec2<u32>, inv_rot: mat3x3<f32>) -> Basis {
+fn oct_mode_normal_from_uv(grid_index: vec2<u32>) -> Basis {
     var n: vec3<f32>;
 
 #ifdef GRID_HEMISPHERICAL
@@ -126,61 +235,142 @@ fn oct_mode_normal_from_uv(grid_index: vec2<u32>, inv_rot: mat3x3<f32>) -> Basis
     let up = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(n.y) > 0.99);
 
     var basis: Basis;
-    basis.normal = inv_rot * n;
-    basis.up = inv_rot * up;
+    basis.normal = n;
+    basis.up = up;
     return basis;
 }
 
-// uv at mid, impact of 1 depth on uv
-fn sample_uvs_unbounded(base_world_position: vec3<f32>, world_position: vec3<f32>, inv_rot: mat3x3<f32>, grid_index: vec2<u32>) -> vec4<f32> {
-    let basis = oct_mode_normal_from_uv(grid_index, inv_rot);
+// uv at mid, impact of 1 depth on uv.
+// positions are in the imposter's local (baked) space, relative to its center
+fn sample_uvs_unbounded(local_position: vec3<f32>, local_camera_position: vec3<f32>, grid_index: vec2<u32>) -> vec4<f32> {
+    let basis = oct_mode_normal_from_uv(grid_index);
     let sample_r_vec = cross(basis.normal, -basis.up);
     let sample_u_vec = cross(sample_r_vec, basis.normal);
     let sample_r = normalize(sample_r_vec);
     let sample_u = normalize(sample_u_vec);
-    let backplane_base_world_position = base_world_position + basis.normal * imposter_data.center_and_scale.w;
+    let backplane_base = basis.normal * imposter_data.center_and_scale.w;
 
-#ifdef VIEW_PROJECTION_ORTHOGRAPHIC
-    let v = world_position - base_world_position;
-    let x = dot(v, sample_r / (imposter_data.center_and_scale.w * 2.0));
-    let y = dot(v, sample_u / (imposter_data.center_and_scale.w * 2.0));
+    var v: vec3<f32>;
+    var backplane_v: vec3<f32>;
+    if view_is_orthographic() {
+        v = local_position;
+        backplane_v = local_position - backplane_base;
+    } else {
+        let cam_to_fragment = normalize(local_position - local_camera_position);
+        let distance = dot(-local_camera_position, basis.normal) / dot(cam_to_fragment, basis.normal);
+        v = distance * cam_to_fragment + local_camera_position;
+
+        let backplane_distance = dot(backplane_base - local_camera_position, basis.normal) / dot(cam_to_fragment, basis.normal);
+        let backplane_intersect = backplane_distance * cam_to_fragment + local_camera_position;
+        backplane_v = backplane_intersect - backplane_base;
+    }
 
-    let backplane_v = world_position - backplane_base_world_position;
-    let backplane_x = dot(backplane_v, sample_r / (imposter_data.center_and_scale.w * 2.0));
-    let backplane_y = dot(backplane_v, sample_u / (imposter_data.center_and_scale.w * 2.0));
-#else
-    let camera_world_position = position_view_to_world(vec3<f32>(0.0));
-    let cam_to_fragment = normalize(world_position - camera_world_position);
-    let distance = dot(base_world_position - camera_world_position, basis.normal) / dot(cam_to_fragment, basis.normal);
-    let intersect = distance * cam_to_fragment + camera_world_position;
     // calculate uv using basis of the sample plane
-    let v = intersect - base_world_position;
     let x = dot(v, sample_r / (imposter_data.center_and_scale.w * 2.0));
     let y = dot(v, sample_u / (imposter_data.center_and_scale.w * 2.0));
-
-    let backplane_distance = dot(backplane_base_world_position - camera_world_position, basis.normal) / dot(cam_to_fragment, basis.normal);
-    let backplane_intersect = backplane_distance * cam_to_fragment + camera_world_position;
-    let backplane_v = backplane_intersect - backplane_base_world_position;
     let backplane_x = dot(backplane_v, sample_r / (imposter_data.center_and_scale.w * 2.0));
     let backplane_y = dot(backplane_v, sample_u / (imposter_data.center_and_scale.w * 2.0));
-#endif
 
     let uv = vec2<f32>(x, y) + 0.5;
     let backplane_uv = vec2<f32>(backplane_x, backplane_y) + 0.5;
     return vec4<f32>(uv, (backplane_uv - uv));
 }
 
-fn single_sample(coords: vec2<f32>, bounds_min: vec2<f32>, bounds_max: vec2<f32>) -> UnpackedMaterialProps {
-#ifdef INDEXED_PIXELS
+// number of tile mip levels. atlas textures only hold the base level
+fn imposter_mip_count() -> u32 {
+#ifdef IMPOSTER_ATLAS
+    return 1u;
+#else ifdef FILTERABLE_PIXELS
+    // the hardware picks the level
+    return 1u;
+#else ifdef INDEXED_PIXELS
+    return textureNumLevels(imposter_indices);
+#else
+    return textureNumLevels(imposter_pixels);
+#endif
+}
+
+// screen-space gradients of a tile's uv (x: xy, y: zw), projecting the position gradients onto the tile plane
+fn tile_uv_gradients(grid_index: vec2<u32>) -> vec4<f32> {
+    let basis = oct_mode_normal_from_uv(grid_index);
+    let sample_r = normalize(cross(basis.normal, -basis.up));
+    let sample_u = normalize(cross(sample_r, basis.normal));
+    let gradients = vec4<f32>(
+        dot(imposter_position_dx, sample_r),
+        dot(imposter_position_dx, sample_u),
+        dot(imposter_position_dy, sample_r),
+        dot(imposter_position_dy, sample_u),
+    );
+    return gradients / (imposter_data.center_and_scale.w * 2.0);
+}
+
+// choose a mip level from the screen-space footprint of the tile uv, in base level pixels
+fn tile_lod(uv_gradients: vec4<f32>) -> u32 {
+    let tile_size = vec2<f32>(imposter_data.base_tile_size);
+    let footprint = max(length(uv_gradients.xy * tile_size), length(uv_gradients.zw * tile_size));
+    let lod = u32(max(log2(footprint), 0.0));
+    return min(lod, imposter_mip_count() - 1u);
+}
+
+#ifdef FILTERABLE_PIXELS
+fn filtered_sample(coords: vec2<f32>, bounds_min: vec2<f32>, bounds_max: vec2<f32>) -> UnpackedMaterialProps {
+    var props: UnpackedMaterialProps;
+    if any(coords < bounds_min) || any(coords >= bounds_max) {
+        return props;
+    }
+
+    // keep the bilinear footprint inside the tile
+    let dims = vec2<f32>(textureDimensions(imposter_albedo));
+    let uv = clamp(coords, bounds_min + 0.5, bounds_max - 0.5) / dims;
+    let ddx = imposter_pixel_gradients.xy / dims;
+    let ddy = imposter_pixel_gradients.zw / dims;
+
+    props.rgba = textureSampleGrad(imposter_albedo, imposter_sampler, uv, ddx, ddy);
+    props.normal = spherical_normal_from_uv(textureSampleGrad(imposter_normal, imposter_sampler, uv, ddx, ddy).rg);
+    let packed = textureSampleGrad(imposter_props, imposter_sampler, uv, ddx, ddy);
+    props.depth = packed.r * 2.0 - 1.0;
+    props.roughness = clamp(packed.g, 0.1, 0.9);
+    props.metallic = clamp(packed.b, 0.1, 0.9);
+    props.flags = u32(packed.a * 15.0 + 0.5);
+    return props;
+}
+#endif
+
+fn single_sample(coords: vec2<f32>, bounds_min: vec2<f32>, bounds_max: vec2<f32>, lod: u32) -> UnpackedMaterialProps {
+#ifdef FILTERABLE_PIXELS
+    return filtered_sample(coords, bounds_min, bounds_max);
+#else
+#ifdef IMPOSTER_ATLAS
+    // atlas entries may use either storage, so we have to branch
+    var packed: vec2<u32>;
+    if (imposter_data.flags & IMPOSTER_INDEXED_FLAG) != 0u {
+        let pixel_dims = imposter_atlas_pixels_size;
+        var index: u32;
+
+        if pixel_dims.x * pixel_dims.y < 65536 {
+            // using u16 pairs
+            let index_pair = textureLoad(imposter_atlas_indices, vec2<u32>(coords * vec2(0.5, 1.0)), imposter_atlas_layer, 0).r;
+            index = select(index_pair & 0xFFFF, index_pair >> 16, (u32(coords.x) & 1u) == 1u);
+        } else {
+            index = textureLoad(imposter_atlas_indices, vec2<u32>(coords), imposter_atlas_layer, 0).r;
+        }
+
+        packed = textureLoad(imposter_atlas_pixels, vec2(index % pixel_dims.x, index / pixel_dims.x), imposter_atlas_layer, 0).rg;
+    } else {
+        packed = textureLoad(imposter_atlas_pixels, vec2<u32>(coords), imposter_atlas_layer, 0).rg;
+    }
+
+    let props = packed * vec2(select(1u, 0u, any(coords < bounds_min) || any(coords >= bounds_max)));
+#else ifdef INDEXED_PIXELS
     let pixel_dims = textureDimensions(imposter_pixels);
     var index: u32;
 
     if pixel_dims.x * pixel_dims.y < 65536 {
         // using u16 pairs
-        let index_pair = textureLoad(imposter_indices, vec2<u32>(coords * vec2(0.5, 1.0)), 0).r;
+        let index_pair = textureLoad(imposter_indices, vec2<u32>(coords * vec2(0.5, 1.0)), i32(lod)).r;
         index = select(index_pair & 0xFFFF, index_pair >> 16, (u32(coords.x) & 1u) == 1u);
     } else {
-        index = textureLoad(imposter_indices, vec2<u32>(coords), 0).r;
+        index = textureLoad(imposter_indices, vec2<u32>(coords), i32(lod)).r;
     }
 
     let index_x = index % pixel_dims.x;
@@ -188,36 +378,92 @@ fn single_sample(coords: vec2<f32>, bounds_min: vec2<f32>, bounds_max: vec2<f32>
 
     let props = textureLoad(imposter_pixels, vec2(index_x, index_y), 0).rg * vec2(select(1u, 0u, any(coords < bounds_min) || any(coords >= bounds_max)));
 #else
-    let props = textureLoad(imposter_pixels, vec2<u32>(coords), 0).rg * vec2(select(1u, 0u, any(coords < bounds_min) || any(coords >= bounds_max)));
+    let props = textureLoad(imposter_pixels, vec2<u32>(coords), i32(lod)).rg * vec2(select(1u, 0u, any(coords < bounds_min) || any(coords >= bounds_max)));
 #endif
     return unpack_props(props);
+#endif
 }
 
-fn sample_tile_material(uv_and_dd: vec4<f32>, grid_index: vec2<u32>, coord_offset: vec2<f32>) -> UnpackedMaterialProps {
-    let bounds_min = vec2<f32>(grid_index * imposter_data.packed_size);
-    let bounds_max = bounds_min + vec2<f32>(imposter_data.packed_size);
-    let coords_unadjusted = bounds_min - vec2<f32>(imposter_data.packed_offset) + uv_and_dd.xy * vec2<f32>(imposter_data.base_tile_size) + coord_offset;
+const PARALLAX_REFINE_STEPS: u32 = 4u;
+
+// march the view ray through the tile, from the front of the bounds (depth 1) to the back (depth -1), returning the
+// depth where it first passes behind the baked surface. the sample position for a depth is `coords + depth * coords_delta`
+fn march_depth(coords: vec2<f32>, coords_delta: vec2<f32>, bounds_min: vec2<f32>, bounds_max: vec2<f32>, lod: u32) -> f32 {
+    let step_size = 2.0 / f32(imposter_data.parallax_steps);
+
+    var front_t = 1.0;
+    var t = 1.0;
+    var hit = false;
+    for (var i = 0u; i <= imposter_data.parallax_steps; i++) {
+        t = 1.0 - f32(i) * step_size;
+        let props = single_sample(coords + t * coords_delta, bounds_min, bounds_max, lod);
+        if props.rgba.a >= 0.01 && props.depth >= t {
+            hit = true;
+            break;
+        }
+        front_t = t;
+    }
 
-#ifdef MATERIAL_MULTISAMPLE
-        // multisample for depth
-        let pixel_tl_depth = single_sample(coords_unadjusted, bounds_min, bounds_max);
-        let pixel_tr_depth = single_sample(coords_unadjusted + vec2(1.0, 0.0), bounds_min, bounds_max);
-        let pixel_bl_depth = single_sample(coords_unadjusted + vec2(0.0, 1.0), bounds_min, bounds_max);
-        let pixel_br_depth = single_sample(coords_unadjusted + vec2(1.0, 1.0), bounds_min, bounds_max);
+    if !hit {
+        // nothing along the ray, fall back to the single step result
+        return single_sample(coords, bounds_min, bounds_max, lod).depth;
+    }
 
-        let frac = fract(coords_unadjusted);
-        let pixel_top_depth = weighted_props(pixel_tl_depth, pixel_tr_depth, 1.0 - frac.x);
-        let pixel_bottom_depth = weighted_props(pixel_bl_depth, pixel_br_depth, 1.0 - frac.x);
-        let pixel_depth = weighted_props(pixel_top_depth, pixel_bottom_depth, 1.0 - frac.y);
-        let depth = pixel_depth.depth;
+    // binary search between the last step in front of the surface and the first behind it
+    var back_t = t;
+    for (var i = 0u; i < PARALLAX_REFINE_STEPS; i++) {
+        let mid = (front_t + back_t) * 0.5;
+        let props = single_sample(coords + mid * coords_delta, bounds_min, bounds_max, lod);
+        if props.rgba.a >= 0.01 && props.depth >= mid {
+            back_t = mid;
+        } else {
+            front_t = mid;
+        }
+    }
+    return back_t;
+}
 
-        let coords = coords_unadjusted + depth * uv_and_dd.zw * vec2<f32>(imposter_data.base_tile_size);
+fn sample_tile_material(uv_and_dd: vec4<f32>, grid_index: vec2<u32>, coord_offset: vec2<f32>) -> UnpackedMaterialProps {
+    // tiles are downsampled separately, halving (rounding down) per level
+    let uv_gradients = tile_uv_gradients(grid_index);
+    let lod = tile_lod(uv_gradients);
+    let tile_size = imposter_data.packed_size >> vec2(lod);
+    let level_scale = vec2<f32>(tile_size) / vec2<f32>(imposter_data.packed_size);
+
+    let bounds_min = vec2<f32>(grid_index * tile_size);
+    let bounds_max = bounds_min + vec2<f32>(tile_size);
+    let coords_unadjusted = bounds_min + (uv_and_dd.xy * vec2<f32>(imposter_data.base_tile_size) - vec2<f32>(imposter_data.packed_offset) + coord_offset) * level_scale;
+    let coords_delta = uv_and_dd.zw * vec2<f32>(imposter_data.base_tile_size) * level_scale;
+#ifdef FILTERABLE_PIXELS
+    let pixel_scale = vec2<f32>(imposter_data.base_tile_size) * level_scale;
+    imposter_pixel_gradients = uv_gradients * vec4(pixel_scale, pixel_scale);
+#endif
+
+#ifdef MATERIAL_MULTISAMPLE
+        var depth: f32;
+        if imposter_data.parallax_steps > 1u {
+            depth = march_depth(coords_unadjusted, coords_delta, bounds_min, bounds_max, lod);
+        } else {
+            // multisample for depth
+            let pixel_tl_depth = single_sample(coords_unadjusted, bounds_min, bounds_max, lod);
+            let pixel_tr_depth = single_sample(coords_unadjusted + vec2(1.0, 0.0), bounds_min, bounds_max, lod);
+            let pixel_bl_depth = single_sample(coords_unadjusted + vec2(0.0, 1.0), bounds_min, bounds_max, lod);
+            let pixel_br_depth = single_sample(coords_unadjusted + vec2(1.0, 1.0), bounds_min, bounds_max, lod);
+
+            let frac = fract(coords_unadjusted);
+            let pixel_top_depth = weighted_props(pixel_tl_depth, pixel_tr_depth, 1.0 - frac.x);
+            let pixel_bottom_depth = weighted_props(pixel_bl_depth, pixel_br_depth, 1.0 - frac.x);
+            let pixel_depth = weighted_props(pixel_top_depth, pixel_bottom_depth, 1.0 - frac.y);
+            depth = pixel_depth.depth;
+        }
+
+        let coords = coords_unadjusted + depth * coords_delta;
 
         // multisample final material
-        let pixel_tl = single_sample(coords, bounds_min, bounds_max);
-        let pixel_tr = single_sample(coords + vec2(1.0, 0.0), bounds_min, bounds_max);
-        let pixel_bl = single_sample(coords + vec2(0.0, 1.0), bounds_min, bounds_max);
-        let pixel_br = single_sample(coords + vec2(1.0, 1.0), bounds_min, bounds_max);
+        let pixel_tl = single_sample(coords, bounds_min, bounds_max, lod);
+        let pixel_tr = single_sample(coords + vec2(1.0, 0.0), bounds_min, bounds_max, lod);
+        let pixel_bl = single_sample(coords + vec2(0.0, 1.0), bounds_min, bounds_max, lod);
+        let pixel_br = single_sample(coords + vec2(1.0, 1.0), bounds_min, bounds_max, lod);
 
         let frac2 = fract(coords);
         let pixel_top = weighted_props(pixel_tl, pixel_tr, 1.0 - frac2.x);
@@ -225,10 +471,14 @@ fn sample_tile_material(uv_and_dd: vec4<f32>, grid_index: vec2<u32>, coord_offse
         let pixel = weighted_props(pixel_top, pixel_bottom, 1.0 - frac2.y);
         return pixel;
 #else
-        let pixel_depth = single_sample(coords_unadjusted, bounds_min, bounds_max);
-        let depth = pixel_depth.depth;
-        let coords = coords_unadjusted + depth * uv_and_dd.zw * vec2<f32>(imposter_data.base_tile_size);
-        let pixel = single_sample(coords, bounds_min, bounds_max);
+        var depth: f32;
+        if imposter_data.parallax_steps > 1u {
+            depth = march_depth(coords_unadjusted, coords_delta, bounds_min, bounds_max, lod);
+        } else {
+            depth = single_sample(coords_unadjusted, bounds_min, bounds_max, lod).depth;
+        }
+        let coords = coords_unadjusted + depth * coords_delta;
+        let pixel = single_sample(coords, bounds_min, bounds_max, lod);
 
         return pixel;
 #endif
@@ -0,0 +1,14 @@
+#define_import_path boimp::imposter_data
+
+// kept free of other imports, so that compute shaders can use it without the view bindings
+
+struct ImposterData {
+    center_and_scale: vec4<f32>,
+    packed_offset: vec2<u32>,
+    packed_size: vec2<u32>,
+    grid_size: u32,
+    base_tile_size: u32,
+    flags: u32,
+    alpha: f32,
+    parallax_steps: u32,
+}
@@ -1,5 +1,5 @@
 #import boimp::shared::{ImposterVertexOut, unpack_pbrinput, weighted_props, pack_pbrinput};
-#import boimp::bindings::{sample_positions_from_camera_dir, sample_tile_material, sample_uvs_unbounded};
+#import boimp::bindings::{sample_positions_from_camera_dir, sample_tile_material, sample_uvs_unbounded, set_position_gradients};
 
 #import bevy_pbr::{
     pbr_types::{pbr_input_new, STANDARD_MATERIAL_FLAGS_UNLIT_BIT},
@@ -8,10 +8,10 @@
 
 @fragment
 fn fragment(in: ImposterVertexOut) -> @location(0) vec2<u32> {
-    let inv_rot = mat3x3(
-        in.inverse_rotation_0c,
-        in.inverse_rotation_1c,
-        in.inverse_rotation_2c,
+    let local_from_world = mat3x3(
+        in.local_from_world_0c,
+        in.local_from_world_1c,
+        in.local_from_world_2c,
     );
 
     let camera_world_position = position_view_to_world(vec3<f32>(0.0));
@@ -23,16 +23,20 @@ fn fragment(in: ImposterVertexOut) -> @location(0) vec2<u32> {
 
     let back = normalize(back_vec);
 
-    let samples = sample_positions_from_camera_dir(back * inv_rot);
+    let samples = sample_positions_from_camera_dir(normalize(local_from_world * back));
 
-    let uv_a = sample_uvs_unbounded(in.base_world_position, in.world_position, inv_rot, samples.tile_indices[0]);
-    let uv_b = sample_uvs_unbounded(in.base_world_position, in.world_position, inv_rot, samples.tile_indices[1]);
+    let local_camera_position = local_from_world * (camera_world_position - in.base_world_position);
+    let local_position = local_from_world * (in.world_position - in.base_world_position);
+    set_position_gradients(local_position);
+
+    let uv_a = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[0]);
+    let uv_b = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[1]);
 
     let props_a = sample_tile_material(uv_a, samples.tile_indices[0], vec2(0.0));
     let props_b = sample_tile_material(uv_b, samples.tile_indices[1], vec2(0.0));
 
 #ifndef GRID_HORIZONTAL
-    let uv_c = sample_uvs_unbounded(in.base_world_position, in.world_position, inv_rot, samples.tile_indices[2]);
+    let uv_c = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[2]);
     let props_c = sample_tile_material(uv_c, samples.tile_indices[2], vec2(0.0));
 #endif
 
@@ -50,7 +54,7 @@ fn fragment(in: ImposterVertexOut) -> @location(0) vec2<u32> {
 
     var pbr_input = unpack_pbrinput(props_final, in.position);
     pbr_input.material.base_color.a = 1.0;
-    pbr_input.N = inv_rot * normalize(pbr_input.N);
+    pbr_input.N = normalize(normalize(pbr_input.N) * local_from_world);
     pbr_input.world_normal = pbr_input.N;
 
     // write the imposter gbuffer
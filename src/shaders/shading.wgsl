@@ -0,0 +1,361 @@
+#define_import_path boimp::shading
+
+// the imposter fragment logic, split so that custom fragment shaders (see `ImposterMaterialExtension`)
+// can adjust the material between sampling and lighting:
+//
+// @fragment
+// fn fragment(in: ImposterVertexOut) -> ImposterFragmentOutput {
+//     var sample = sample_imposter(in);
+//     // modify sample.props / sample.world_normal here
+//     return imposter_fragment_output(in, sample);
+// }
+
+#import bevy_pbr::{
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+    pbr_types::{STANDARD_MATERIAL_FLAGS_UNLIT_BIT, STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT},
+    view_transformations::{direction_view_to_world, position_view_to_world, position_world_to_clip}
+}
+
+#import boimp::shared::{ImposterVertexOut, ImposterFragmentOutput, UnpackedMaterialProps, IMPOSTER_MATERIAL_UNLIT, unpack_pbrinput, weighted_props, view_is_orthographic};
+#ifdef PREPASS_PIPELINE
+#ifdef DEFERRED_PREPASS
+    #import bevy_pbr::pbr_deferred_functions::deferred_gbuffer_from_pbr_input;
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    #import bevy_pbr::{mesh_view_bindings::view, prepass_bindings::previous_view_uniforms};
+    #import boimp::shared::{affine_inverse, linear_inverse};
+    #ifndef IMPOSTER_ATLAS
+        #import bevy_pbr::mesh_functions;
+    #endif
+#endif
+#endif
+#import boimp::bindings::{imposter_data, SamplePositions, sample_positions_from_camera_dir, billboard_tile_dir, sort_samples_by_weight, sample_uvs_unbounded, sample_tile, sample_tile_material, set_position_gradients};
+#ifdef IMPOSTER_ATLAS
+    #import boimp::bindings::set_atlas_entry;
+#endif
+
+struct ImposterMaterialSample {
+    // blended material of the nearest tiles. the normal is in the imposter's local space
+    props: UnpackedMaterialProps,
+    // normal used for lighting
+    world_normal: vec3<f32>,
+    // the baked surface point
+    world_position: vec3<f32>,
+    depth_ndc: f32,
+}
+
+// flat unlit color for the `ImposterDebugMode` views
+fn debug_props(props: UnpackedMaterialProps, color: vec3<f32>) -> UnpackedMaterialProps {
+    var debug = props;
+    debug.rgba = vec4(color, 1.0);
+    debug.flags = IMPOSTER_MATERIAL_UNLIT;
+    return debug;
+}
+
+// tiles ordered for the blend mode, highest weight first when not all three are blended
+fn blend_order(samples: SamplePositions) -> SamplePositions {
+#ifdef TILE_BLEND_NEAREST
+    return sort_samples_by_weight(samples);
+#else ifdef TILE_BLEND_TWO
+    return sort_samples_by_weight(samples);
+#else
+    return samples;
+#endif
+}
+
+// sample the tiles along the ray from the camera through `local_position`, and blend them by weight
+fn blend_tiles(local_position: vec3<f32>, local_camera_position: vec3<f32>, samples: SamplePositions) -> UnpackedMaterialProps {
+    let uv_a = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[0]);
+    let props_a = sample_tile_material(uv_a, samples.tile_indices[0], vec2(0.0));
+
+#ifdef TILE_BLEND_NEAREST
+    return props_a;
+#else
+    let uv_b = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[1]);
+    let props_b = sample_tile_material(uv_b, samples.tile_indices[1], vec2(0.0));
+
+    let weights = samples.tile_weights;
+    let props_ab = weighted_props(props_a, props_b, weights.x / max(weights.x + weights.y, 0.0001));
+#ifdef GRID_HORIZONTAL
+    return props_ab;
+#else ifdef TILE_BLEND_TWO
+    return props_ab;
+#else
+    let uv_c = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[2]);
+    let props_c = sample_tile_material(uv_c, samples.tile_indices[2], vec2(0.0));
+    return weighted_props(props_ab, props_c, (weights.x + weights.y) / (weights.x + weights.y + weights.z));
+#endif
+#endif
+}
+
+#ifdef IMPOSTER_WIND
+// shift the lookup against the wind (`local_wind`, in local space), bending more towards the top of the imposter
+// bounds. the bend is weighted by the height of the baked surface: a first lookup bent by the quad height finds
+// the surface, which is then bent by its own height
+fn bend_with_wind(quad_local_position: vec3<f32>, local_camera_position: vec3<f32>, local_back: vec3<f32>, samples: SamplePositions, local_wind: vec3<f32>) -> vec3<f32> {
+    let radius = imposter_data.center_and_scale.w;
+    let quad_height = saturate(quad_local_position.y / (2.0 * radius) + 0.5);
+    let local_position = quad_local_position - local_wind * quad_height * quad_height;
+    if all(local_wind == vec3(0.0)) {
+        return local_position;
+    }
+
+    let props = blend_tiles(local_position, local_camera_position, samples);
+    if props.rgba.a < 0.01 {
+        return local_position;
+    }
+    let surface = local_position + normalize(local_back) * props.depth * radius;
+    let height = saturate(surface.y / (2.0 * radius) + 0.5);
+    return quad_local_position - local_wind * height * height;
+}
+#endif
+
+// sample and blend the tiles. discards empty pixels, and pixels behind the depth prepass
+fn sample_imposter(in: ImposterVertexOut) -> ImposterMaterialSample {
+#ifdef IMPOSTER_ATLAS
+    set_atlas_entry(in.atlas_index);
+#endif
+
+    let local_from_world = mat3x3(
+        in.local_from_world_0c,
+        in.local_from_world_1c,
+        in.local_from_world_2c,
+    );
+
+    let camera_world_position = position_view_to_world(vec3<f32>(0.0));
+    // in shadow views this is the light direction, or the direction to the light for point/spot lights
+    var back_vec = camera_world_position - in.base_world_position;
+    if view_is_orthographic() {
+        back_vec = direction_view_to_world(vec3<f32>(0.0, 0.0, 1.0));
+    }
+
+    let back = normalize(back_vec);
+    // not normalized, the length converts baked (local) depth to world units below
+    let local_back = local_from_world * back;
+
+#ifdef VERTEX_TILES
+    var samples: SamplePositions;
+    for (var i = 0; i < 3; i++) {
+        samples.tile_indices[i] = vec2<u32>(in.tile_indices[i] & 0xffffu, in.tile_indices[i] >> 16u);
+    }
+    samples.tile_weights = in.tile_weights;
+#else
+    var samples = sample_positions_from_camera_dir(billboard_tile_dir(normalize(local_back)));
+#endif
+
+    samples = blend_order(samples);
+
+    // sample in the imposter's local space, so that instance scale (including non-uniform) squashes the result
+    let local_camera_position = local_from_world * (camera_world_position - in.base_world_position);
+    let quad_local_position = local_from_world * (in.world_position - in.base_world_position);
+    // the bend follows the baked surface, so take the gradients from the quad before it
+    set_position_gradients(quad_local_position);
+#ifdef IMPOSTER_WIND
+    let local_position = bend_with_wind(quad_local_position, local_camera_position, local_back, samples, local_from_world * in.wind_offset);
+#else
+    let local_position = quad_local_position;
+#endif
+
+    let props_final = blend_tiles(local_position, local_camera_position, samples);
+
+#ifdef IMPOSTER_DEBUG_BOUNDS
+    // keep empty pixels, to show where the quad reaches outside the primary tile's packed bounds
+    let uv_a = sample_uvs_unbounded(local_position, local_camera_position, samples.tile_indices[0]);
+    let packed_uv = (uv_a.xy * vec2<f32>(imposter_data.base_tile_size) - vec2<f32>(imposter_data.packed_offset)) / vec2<f32>(imposter_data.packed_size);
+    let in_bounds = all(packed_uv >= vec2(0.0)) && all(packed_uv <= vec2(1.0));
+#else
+    if props_final.rgba.a < 0.01 {
+        discard;
+    }
+#endif
+
+    let imposted_world_position = in.world_position + back * props_final.depth * imposter_data.center_and_scale.w / length(local_back);
+    let imposted_ndc = position_world_to_clip(imposted_world_position);
+
+    var sample: ImposterMaterialSample;
+    sample.props = props_final;
+    // normals transform by the inverse transpose
+    sample.world_normal = normalize(normalize(props_final.normal) * local_from_world);
+    sample.world_position = imposted_world_position;
+    sample.depth_ndc = imposted_ndc.z / imposted_ndc.w;
+
+#ifdef IMPOSTER_DEBUG_TILES
+    // the highest weighted tile. red and green map the grid position, blue alternates between neighbours
+    let dominant = sort_samples_by_weight(samples).tile_indices[0];
+    let checker = f32((dominant.x + dominant.y) & 1u);
+    sample.props = debug_props(sample.props, vec3((vec2<f32>(dominant) + 0.5) / f32(imposter_data.grid_size), 0.25 + 0.5 * checker));
+#else ifdef IMPOSTER_DEBUG_WEIGHTS
+    // weights of the tiles actually blended, in rgb
+    var used_weights = samples.tile_weights;
+#ifdef TILE_BLEND_NEAREST
+    used_weights = vec3(1.0, 0.0, 0.0);
+#else ifdef TILE_BLEND_TWO
+    used_weights.z = 0.0;
+#endif
+    sample.props = debug_props(sample.props, used_weights / max(dot(used_weights, vec3(1.0)), 0.0001));
+#else ifdef IMPOSTER_DEBUG_DEPTH
+    // baked depth, black at the back of the bounds and white at the front
+    sample.props = debug_props(sample.props, vec3(saturate(props_final.depth * 0.5 + 0.5)));
+#else ifdef IMPOSTER_DEBUG_NORMALS
+    // unpacked normal, in the imposter's local space
+    sample.props = debug_props(sample.props, normalize(props_final.normal) * 0.5 + 0.5);
+#else ifdef IMPOSTER_DEBUG_BOUNDS
+    // red: clipped by the packed bounds, blue: inside but empty, green: baked pixels
+    var bounds_color = vec3(0.0, 0.0, 0.5);
+    if !in_bounds {
+        bounds_color = vec3(1.0, 0.0, 0.0);
+    } else if props_final.rgba.a >= 0.01 {
+        bounds_color = vec3(0.0, 1.0, 0.0);
+    }
+    if props_final.rgba.a < 0.01 {
+        // empty pixels have no normal
+        sample.world_normal = back;
+    }
+    sample.props = debug_props(sample.props, bounds_color);
+#else ifdef IMPOSTER_DEBUG_STORAGE
+    // blue: indexed, yellow: filterable, green: flat. shaded by the base color's luminance
+#ifdef INDEXED_PIXELS
+    let storage_color = vec3(0.2, 0.4, 1.0);
+#else ifdef FILTERABLE_PIXELS
+    let storage_color = vec3(1.0, 0.9, 0.2);
+#else
+    let storage_color = vec3(0.3, 1.0, 0.3);
+#endif
+    let luminance = dot(props_final.rgba.rgb, vec3(0.2126, 0.7152, 0.0722));
+    sample.props = debug_props(sample.props, storage_color * (0.25 + 0.75 * luminance));
+#endif
+
+// we can discard based on actual depth if we have the depth prepass data
+#ifdef DEPTH_PREPASS
+#ifndef PREPASS_PIPELINE
+    let existing_depth_ndc = bevy_pbr::prepass_utils::prepass_depth(in.position, 0u);
+    
+    if sample.depth_ndc < existing_depth_ndc {
+#ifdef IMPOSTER_DEBUG_DISCARD
+        // show the pixels that would be discarded
+        sample.props = debug_props(sample.props, vec3(1.0, 0.0, 1.0));
+#else
+        discard;
+#endif
+    }
+#endif
+#endif
+
+    return sample;
+}
+
+#ifdef PREPASS_PIPELINE
+#ifdef MOTION_VECTOR_PREPASS
+// where last frame's view showed the baked surface at `world_position`. the tiles are selected, blended and
+// reprojected with last frame's transform and view, so that changes in the blend show as motion
+fn previous_imposted_world_position(in: ImposterVertexOut, world_position: vec3<f32>) -> vec3<f32> {
+    let local_from_world = mat3x3(
+        in.local_from_world_0c,
+        in.local_from_world_1c,
+        in.local_from_world_2c,
+    );
+#ifdef IMPOSTER_ATLAS
+    // atlas instances don't keep a previous transform
+    let previous_base_world_position = in.base_world_position;
+    let previous_world_from_local = linear_inverse(local_from_world);
+    let previous_local_from_world = local_from_world;
+#else
+    let previous_model = mesh_functions::get_previous_world_from_local(in.instance_index);
+    let previous_base_world_position = (previous_model * vec4<f32>(imposter_data.center_and_scale.xyz, 1.0)).xyz;
+    let previous_world_from_local = mat3x3(previous_model[0].xyz, previous_model[1].xyz, previous_model[2].xyz);
+    let previous_local_from_world = linear_inverse(previous_world_from_local);
+#endif
+    // the surface point moves with the instance
+    let previous_surface = previous_base_world_position + previous_world_from_local * (local_from_world * (world_position - in.base_world_position));
+
+    let previous_world_from_view = affine_inverse(previous_view_uniforms.view_from_world);
+    let previous_camera_world_position = previous_world_from_view[3].xyz;
+    let previous_view_back = normalize(previous_world_from_view[2].xyz);
+
+    // same selection as `sample_imposter`, from last frame's camera
+    var previous_back = normalize(previous_camera_world_position - previous_base_world_position);
+    var ray = normalize(previous_surface - previous_camera_world_position);
+    if view_is_orthographic() {
+        previous_back = previous_view_back;
+        ray = -previous_view_back;
+    }
+    let previous_local_back = previous_local_from_world * previous_back;
+
+    // last frame's quad faced the view through the imposter center
+    let quad_distance = dot(previous_base_world_position - previous_surface, previous_view_back) / dot(ray, previous_view_back);
+    let previous_quad = previous_surface + ray * quad_distance;
+
+    let previous_local_camera_position = previous_local_from_world * (previous_camera_world_position - previous_base_world_position);
+    let samples = blend_order(sample_positions_from_camera_dir(billboard_tile_dir(normalize(previous_local_back))));
+    var previous_local_position = previous_local_from_world * (previous_quad - previous_base_world_position);
+#ifdef IMPOSTER_WIND
+    previous_local_position = bend_with_wind(previous_local_position, previous_local_camera_position, previous_local_back, samples, previous_local_from_world * in.wind_offset);
+#endif
+    let props = blend_tiles(previous_local_position, previous_local_camera_position, samples);
+    if props.rgba.a < 0.01 {
+        // the point was not visible through last frame's tiles, fall back to the instance motion
+        return previous_surface;
+    }
+
+    return previous_quad + previous_back * props.depth * imposter_data.center_and_scale.w / length(previous_local_back);
+}
+#endif
+#endif
+
+// write the depth, and apply lighting or fill the prepass outputs
+fn imposter_fragment_output(in: ImposterVertexOut, sample: ImposterMaterialSample) -> ImposterFragmentOutput {
+    var out: ImposterFragmentOutput;
+
+#ifdef WRITE_DEPTH
+    out.frag_depth = clamp(sample.depth_ndc, 0.0, 1.0);
+#endif
+
+    var pbr_input = unpack_pbrinput(sample.props, in.position);
+    pbr_input.N = sample.world_normal;
+    pbr_input.world_normal = pbr_input.N;
+
+    pbr_input.material.base_color.a *= imposter_data.alpha;
+
+#ifdef PREPASS_PIPELINE
+    #ifdef NORMAL_PREPASS
+        out.normal = vec4<f32>(pbr_input.N, 0.0);
+    #endif
+    #ifdef MOTION_VECTOR_PREPASS
+        // reproject the baked surface point rather than the camera-facing quad
+        let world_position = vec4<f32>(sample.world_position, 1.0);
+        let previous_world_position = vec4<f32>(previous_imposted_world_position(in, sample.world_position), 1.0);
+
+        let clip_position_t = view.unjittered_clip_from_world * world_position;
+        let clip_position = clip_position_t.xy / clip_position_t.w;
+        let previous_clip_position_t = previous_view_uniforms.clip_from_world * previous_world_position;
+        let previous_clip_position = previous_clip_position_t.xy / previous_clip_position_t.w;
+        // same scaling as bevy's prepass: clip space is [-2, 2] and uv y goes down
+        out.motion_vector = (clip_position - previous_clip_position) * vec2(0.5, -0.5);
+    #endif
+    #ifdef DEFERRED_PREPASS
+        out.deferred = deferred_gbuffer_from_pbr_input(pbr_input);
+        out.deferred_lighting_pass_id = pbr_input.material.deferred_lighting_pass_id;
+    #endif
+    #ifndef WRITE_DEPTH
+    #ifdef DEPTH_CLAMP_ORTHO
+        out.frag_depth = in.position.z;
+    #else ifndef PREPASS_FRAGMENT
+        out.frag_depth = in.position.z;
+    #endif
+    #endif
+#else 
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+
+    pbr_input.material.flags |= STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT;
+
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    // out.color = clamp(out.color, vec4<f32>(0.2, 0.0, 0.0, 0.2), vec4<f32>(1.0, 1.0, 1.0, 0.7));
+
+    return out;
+}
@@ -1,5 +1,8 @@
 #define_import_path boimp::shared
 
+// part of the shader api for custom imposter shading: `ImposterVertexOut`, `ImposterFragmentOutput`
+// and `UnpackedMaterialProps` are kept stable (`ImposterData` is in `boimp::imposter_data`)
+
 #import bevy_pbr::{
     pbr_types::{PbrInput, STANDARD_MATERIAL_FLAGS_UNLIT_BIT, pbr_input_new},
     view_transformations::{position_ndc_to_world, frag_coord_to_ndc},
@@ -10,23 +13,73 @@
 const IMPOSTER_MATERIAL_UNLIT: u32 = 1;
 const IMPOSTER_MATERIAL_EMISSIVE: u32 = 2;
 
-struct ImposterData {
-    center_and_scale: vec4<f32>,
-    packed_offset: vec2<u32>,
-    packed_size: vec2<u32>,
-    grid_size: u32,
-    base_tile_size: u32,
-    flags: u32,
-    alpha: f32,
+// must match the flags in render.rs
+const IMPOSTER_INDEXED_FLAG: u32 = 32;
+const IMPOSTER_WIND_FLAG: u32 = 8192;
+
+// shadow views don't carry the projection defs, so fall back to checking the projection matrix
+fn view_is_orthographic() -> bool {
+#ifdef VIEW_PROJECTION_ORTHOGRAPHIC
+    return true;
+#else ifdef VIEW_PROJECTION_PERSPECTIVE
+    return false;
+#else
+    return view.clip_from_view[3].w == 1.0;
+#endif
 }
 
 struct ImposterVertexOut {
     @builtin(position) position: vec4<f32>,
     @location(0) world_position: vec3<f32>,
     @location(1) base_world_position: vec3<f32>,
-    @location(2) inverse_rotation_0c: vec3<f32>,
-    @location(3) inverse_rotation_1c: vec3<f32>,
-    @location(4) inverse_rotation_2c: vec3<f32>,
+    // linear part of the inverse model transform, including scale
+    @location(2) local_from_world_0c: vec3<f32>,
+    @location(3) local_from_world_1c: vec3<f32>,
+    @location(4) local_from_world_2c: vec3<f32>,
+#ifdef IMPOSTER_ATLAS
+    @location(5) @interpolate(flat) atlas_index: u32,
+#endif
+#ifdef PREPASS_PIPELINE
+#ifdef MOTION_VECTOR_PREPASS
+    @location(6) @interpolate(flat) instance_index: u32,
+#endif
+#endif
+#ifdef VERTEX_TILES
+    // tile x | y << 16
+    @location(7) @interpolate(flat) tile_indices: vec3<u32>,
+    @location(8) @interpolate(flat) tile_weights: vec3<f32>,
+#endif
+#ifdef IMPOSTER_WIND
+    @location(9) @interpolate(flat) wind_offset: vec3<f32>,
+#endif
+}
+
+// replaces the bevy FragmentOutputs so that we can write depth
+struct ImposterFragmentOutput {
+#ifdef PREPASS_PIPELINE
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+#ifdef DEFERRED_PREPASS
+    @location(2) deferred: vec4<u32>,
+    @location(3) deferred_lighting_pass_id: u32,
+#endif
+#else
+    @location(0) color: vec4<f32>,
+#endif
+#ifdef WRITE_DEPTH
+    @builtin(frag_depth) frag_depth: f32,
+#else ifdef PREPASS_PIPELINE
+#ifdef DEPTH_CLAMP_ORTHO
+    @builtin(frag_depth) frag_depth: f32,
+#else ifndef PREPASS_FRAGMENT
+    // a depth-only prepass has no other outputs, and the struct can't be empty
+    @builtin(frag_depth) frag_depth: f32,
+#endif
+#endif
 }
 
 struct UnpackedMaterialProps {
@@ -43,7 +96,9 @@ fn spherical_uv_from_normal(dir: vec3<f32>) -> vec2<f32> {
     let sum: f32 = dot(dir, octant);
     let octahedron: vec3<f32> = dir / sum;
     let absolute: vec3<f32> = abs(octahedron);
-    return (select(octahedron.xz, octant.xz * vec2(1.0 - absolute.z, 1.0 - absolute.x), octahedron.y < 0.0) + 1.0) * 0.5;
+    // fold the lower half by quadrant, counting 0 as positive so that straight down reaches a corner
+    let fold = select(vec2(-1.0), vec2(1.0), octahedron.xz >= vec2(0.0));
+    return (select(octahedron.xz, fold * vec2(1.0 - absolute.z, 1.0 - absolute.x), octahedron.y < 0.0) + 1.0) * 0.5;
 }
 
 fn spherical_normal_from_uv(uv: vec2<f32>) -> vec3<f32> {
@@ -59,6 +114,24 @@ fn spherical_normal_from_uv(uv: vec2<f32>) -> vec3<f32> {
     return normalize(n);
 }
 
+// inverse of an affine transform
+fn linear_inverse(m: mat3x3<f32>) -> mat3x3<f32> {
+    let c0 = cross(m[1], m[2]);
+    let c1 = cross(m[2], m[0]);
+    let c2 = cross(m[0], m[1]);
+    return transpose(mat3x3(c0, c1, c2)) * (1.0 / dot(m[0], c0));
+}
+
+fn affine_inverse(m: mat4x4<f32>) -> mat4x4<f32> {
+    let inv_linear = linear_inverse(mat3x3(m[0].xyz, m[1].xyz, m[2].xyz));
+    return mat4x4(
+        vec4(inv_linear[0], 0.0),
+        vec4(inv_linear[1], 0.0),
+        vec4(inv_linear[2], 0.0),
+        vec4(-(inv_linear * m[3].xyz), 1.0),
+    );
+}
+
 fn normalize_or_zero(in: vec3<f32>) -> vec3<f32> {
     let len = length(in);
     return select(in / len, vec3(0.0), len < 0.00001);
@@ -1,59 +1,144 @@
 #import bevy_pbr::{
-    mesh_functions,
     view_transformations::{position_world_to_clip, position_view_to_world, direction_view_to_world, perspective_camera_near},
 }
 
-#ifdef PREPASS_PIPELINE
-    #import bevy_pbr::prepass_io::Vertex;
+#ifdef IMPOSTER_WIND
+    #import boimp::bindings::imposter_wind;
+    #ifdef IMPOSTER_ATLAS
+        #import boimp::shared::IMPOSTER_WIND_FLAG;
+    #endif
+#endif
+
+#ifdef IMPOSTER_ATLAS
+    #import boimp::bindings::{imposter_atlas_instances, set_atlas_entry};
+    #ifdef IMPOSTER_ATLAS_INDIRECT
+        #import boimp::bindings::imposter_atlas_visible;
+    #endif
 #else
-    #import bevy_pbr::forward_io::Vertex;
+    #import bevy_pbr::mesh_functions;
+
+    #ifdef PREPASS_PIPELINE
+        #import bevy_pbr::prepass_io::Vertex;
+    #else
+        #import bevy_pbr::forward_io::Vertex;
+    #endif
 #endif
 
-#import boimp::shared::ImposterVertexOut;
-#import boimp::bindings::{imposter_data, sample_uvs_unbounded, grid_weights, sample_positions_from_camera_dir};
+#import boimp::shared::{ImposterVertexOut, view_is_orthographic, linear_inverse};
+#import boimp::bindings::{imposter_data, sample_uvs_unbounded, grid_weights, sample_positions_from_camera_dir, billboard_tile_dir};
+
+#ifdef IMPOSTER_ATLAS
+@vertex
+fn vertex(@builtin(vertex_index) vertex_index: u32, @builtin(instance_index) instance_index: u32) -> ImposterVertexOut {
+#ifdef IMPOSTER_ATLAS_INDIRECT
+    let region = #{IMPOSTER_ATLAS_GRID_MODE}u * arrayLength(&imposter_atlas_instances);
+    let instance = imposter_atlas_instances[imposter_atlas_visible[region + instance_index]];
+#else
+    let instance = imposter_atlas_instances[instance_index];
+#endif
+    set_atlas_entry(instance.index);
 
+    // two ccw triangles covering a unit quad centered on the origin
+    let corner = vec2<f32>(vec2((0x32u >> vertex_index) & 1u, (0x2cu >> vertex_index) & 1u));
+    var out = imposter_vertex(instance.world_from_local, vec3<f32>(corner - 0.5, 0.0));
+    out.atlas_index = instance.index;
+    return out;
+}
+#else
 @vertex
 fn vertex(vertex: Vertex) -> ImposterVertexOut {
-    var out: ImposterVertexOut;
+    let model = mesh_functions::get_world_from_local(vertex.instance_index);
+    var out = imposter_vertex(model, vertex.position);
+#ifdef PREPASS_PIPELINE
+#ifdef MOTION_VECTOR_PREPASS
+    out.instance_index = vertex.instance_index;
+#endif
+#endif
+    return out;
+}
+#endif
 
-    var model = mesh_functions::get_world_from_local(vertex.instance_index);
+fn imposter_vertex(model: mat4x4<f32>, position: vec3<f32>) -> ImposterVertexOut {
+    var out: ImposterVertexOut;
 
     let center = imposter_data.center_and_scale.xyz;
-    let scale = imposter_data.center_and_scale.w;
+    // the quad has to cover the bounds under the largest axis scale, non-uniform scale is applied when sampling
+    let max_scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let scale = imposter_data.center_and_scale.w * max_scale;
 
-    let imposter_world_position = mesh_functions::mesh_position_local_to_world(model, vec4<f32>(center, 1.0)).xyz;
+    let imposter_world_position = (model * vec4<f32>(center, 1.0)).xyz;
     let camera_world_position = position_view_to_world(vec3<f32>(0.0));
 
-    // extract inverse rotation
-    let inv_rot = transpose(mat3x3<f32>(
-        normalize(model[0].xyz),
-        normalize(model[1].xyz),
-        normalize(model[2].xyz)
-    ));
+    let local_from_world = linear_inverse(mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz));
     // todo: we could pass the instance index instead, and extract in frag shader
-    out.inverse_rotation_0c = inv_rot[0];
-    out.inverse_rotation_1c = inv_rot[1];
-    out.inverse_rotation_2c = inv_rot[2];
+    out.local_from_world_0c = local_from_world[0];
+    out.local_from_world_1c = local_from_world[1];
+    out.local_from_world_2c = local_from_world[2];
     out.base_world_position = imposter_world_position;
 
-    let up = vec3<f32>(0.0, 1.0, 0.0);
     let back = direction_view_to_world(vec3<f32>(0.0, 0.0, 1.0));
+    // directional shadow views often look straight down
+    let up = select(vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 0.0, 1.0), abs(back.y) > 0.99);
     let right = cross(up, back);
     let up2 = cross(back, right);
 
-    let view_matrix = transpose(mat3x3(
-        normalize(right), 
-        normalize(up2), 
-        back
-    ));
-    out.world_position = imposter_world_position + (vertex.position * scale * 2.0) * view_matrix;
+    var quad_right = normalize(right);
+    var quad_up = normalize(up2);
+    var quad_position = position * scale * 2.0;
+#ifdef AXIS_LOCKED
+    // only turn around the object's up axis
+    let object_up = normalize(model[1].xyz);
+    let elevation = dot(back, object_up);
+    // looking along the axis there's nothing to lock to, so keep facing the camera. orthographic views do too,
+    // as the tiles are then sampled along the view direction (see `billboard_tile_dir`)
+    if abs(elevation) < 0.99 && !view_is_orthographic() {
+        quad_right = normalize(cross(object_up, back));
+        quad_up = object_up;
+        // an upright quad has to be 1 / cos(elevation) tall to cover the bounding sphere seen from a distance
+        quad_position.y /= sqrt(1.0 - elevation * elevation);
+    }
+#endif
+    out.world_position = imposter_world_position + quad_position.x * quad_right + quad_position.y * quad_up + quad_position.z * back;
+
+#ifdef IMPOSTER_WIND
+    // per-instance phase from the position, so neighbours don't sway in sync
+    let phase = fract(sin(dot(imposter_world_position.xz, vec2(12.9898, 78.233))) * 43758.5453) * 6.283185307;
+    // lean downwind and oscillate around it
+    let sway = 0.6 + 0.4 * sin(imposter_wind.w + phase);
+    out.wind_offset = imposter_wind.xyz * scale * sway;
+#ifdef IMPOSTER_ATLAS
+    // atlas pipelines are shared by all entries, so wind is enabled per entry
+    if (imposter_data.flags & IMPOSTER_WIND_FLAG) == 0u {
+        out.wind_offset = vec3(0.0);
+    }
+#endif
+#endif
+
+#ifdef VERTEX_TILES
+    // tiles only depend on the direction to the imposter center, so select them once here
+    var tile_dir = normalize(camera_world_position - imposter_world_position);
+    if view_is_orthographic() {
+        tile_dir = back;
+    }
+    let samples = sample_positions_from_camera_dir(billboard_tile_dir(normalize(local_from_world * tile_dir)));
+    out.tile_indices = vec3<u32>(
+        samples.tile_indices[0].x | (samples.tile_indices[0].y << 16u),
+        samples.tile_indices[1].x | (samples.tile_indices[1].y << 16u),
+        samples.tile_indices[2].x | (samples.tile_indices[2].y << 16u),
+    );
+    out.tile_weights = samples.tile_weights;
+#endif
+
+    if view_is_orthographic() {
+        out.position = position_world_to_clip(out.world_position);
+        return out;
+    }
 
-#ifndef VIEW_PROJECTION_ORTHOGRAPHIC
     // project the actual frag position to the furthest of the front plane of the imposter, and the camera near plane * 0.9
     let ray_direction = normalize(camera_world_position - out.world_position);
     let plane_normal = direction_view_to_world(vec3<f32>(0.0, 0.0, 1.0));
 
-    let imposter_front_plane_origin = imposter_world_position + plane_normal * imposter_data.center_and_scale.w;
+    let imposter_front_plane_origin = imposter_world_position + plane_normal * scale;
     let imposter_front_plane_distance = dot(imposter_front_plane_origin - out.world_position, plane_normal) / dot(ray_direction, plane_normal);
 
     let camera_near_plane_origin = camera_world_position - perspective_camera_near() * back;
@@ -63,9 +148,6 @@ fn vertex(vertex: Vertex) -> ImposterVertexOut {
 
     let point_on_plane = out.world_position + plane_distance * 1.0 * ray_direction;
     out.position = position_world_to_clip(point_on_plane);
-#else
-    out.position = position_world_to_clip(out.world_position);
-#endif
 
     return out;
 }
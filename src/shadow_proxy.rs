@@ -0,0 +1,98 @@
+use bevy::{
+    ecs::entity::{EntityHashMap, EntityHashSet},
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::view::VisibleEntities,
+};
+
+/// renders the entity's imposter only into shadow views, casting shadows on behalf of `source`.
+/// `source` and any meshes below it are marked `NotShadowCaster` until the proxy is removed, and the
+/// proxy is left out of camera views so it is only queued for shadow maps.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct ImposterShadowProxy {
+    pub source: Entity,
+}
+
+/// removes proxies from camera views, so they are never queued in the main, prepass or deferred phases.
+/// lights collect their own visible entities, so shadow views still see them
+pub fn hide_shadow_proxies(
+    proxies: Query<(), With<ImposterShadowProxy>>,
+    mut views: Query<&mut VisibleEntities>,
+) {
+    if proxies.is_empty() {
+        return;
+    }
+
+    for mut visible in views.iter_mut() {
+        visible
+            .get_mut::<With<Mesh3d>>()
+            .retain(|entity| !proxies.contains(*entity));
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn update_shadow_proxies(
+    mut commands: Commands,
+    proxies: Query<(Entity, Ref<ImposterShadowProxy>)>,
+    mut removed: RemovedComponents<ImposterShadowProxy>,
+    changed_children: Query<Entity, Changed<Children>>,
+    parents: Query<&Parent>,
+    children: Query<&Children>,
+    casters: Query<Has<NotShadowCaster>, (With<Mesh3d>, Without<ImposterShadowProxy>)>,
+    // entities marked by each proxy, so that `NotShadowCaster`s added by the user are left alone
+    mut hidden: Local<EntityHashMap<EntityHashSet>>,
+) {
+    // removed proxies and proxies with a new source give their entities back
+    let mut released = EntityHashSet::default();
+    for entity in removed.read() {
+        released.extend(hidden.remove(&entity).unwrap_or_default());
+    }
+    for (entity, proxy) in proxies.iter() {
+        if proxy.is_changed() {
+            released.extend(hidden.remove(&entity).unwrap_or_default());
+        }
+    }
+
+    let mut hide = |hidden: &mut EntityHashMap<EntityHashSet>, proxy: Entity, root: Entity| {
+        for entity in std::iter::once(root).chain(children.iter_descendants(root)) {
+            let Ok(marked) = casters.get(entity) else {
+                continue;
+            };
+            let ours = released.contains(&entity) || hidden.values().any(|h| h.contains(&entity));
+            if !marked {
+                commands.entity(entity).insert(NotShadowCaster);
+            }
+            if !marked || ours {
+                hidden.entry(proxy).or_default().insert(entity);
+            }
+        }
+    };
+
+    for (entity, proxy) in proxies.iter() {
+        if proxy.is_changed() {
+            hide(&mut hidden, entity, proxy.source);
+        }
+    }
+
+    // scenes spawn their meshes later, so check hierarchies as they change
+    let mut sources = EntityHashMap::<Vec<Entity>>::default();
+    for (entity, proxy) in proxies.iter() {
+        sources.entry(proxy.source).or_default().push(entity);
+    }
+    for entity in changed_children.iter() {
+        for ancestor in std::iter::once(entity).chain(parents.iter_ancestors(entity)) {
+            for proxy in sources.get(&ancestor).into_iter().flatten() {
+                hide(&mut hidden, *proxy, entity);
+            }
+        }
+    }
+
+    for entity in released {
+        if hidden.values().any(|h| h.contains(&entity)) {
+            continue;
+        }
+        if let Some(mut commands) = commands.get_entity(entity) {
+            commands.remove::<NotShadowCaster>();
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::hash::Hash;
+
+use bevy::{
+    pbr::{
+        DrawPrepass, ExtractedDirectionalLight, ExtractedPointLight, LightEntity,
+        MaterialPipelineKey, MeshPipelineKey, PreparedMaterial, PrepassPipeline,
+        RenderCascadesVisibleEntities, RenderCubemapVisibleEntities, RenderMaterialInstances,
+        RenderMeshInstanceFlags, RenderMeshInstances, RenderVisibleMeshEntities, Shadow,
+        ShadowBinKey, ViewLightEntities,
+    },
+    prelude::*,
+    render::{
+        mesh::RenderMesh,
+        render_asset::RenderAssets,
+        render_phase::{BinnedRenderPhaseType, DrawFunctions, ViewBinnedRenderPhases},
+        render_resource::{PipelineCache, SpecializedMeshPipelines},
+    },
+};
+
+use crate::render::{ImposterKey, SHADOW_VIEW_KEY};
+
+/// material keys that can mark a pipeline as drawing into a shadow view
+pub trait ImposterShadowKey {
+    fn for_shadow_view(&self) -> Self;
+}
+
+impl ImposterShadowKey for ImposterKey {
+    fn for_shadow_view(&self) -> Self {
+        Self(self.0 | SHADOW_VIEW_KEY, self.1)
+    }
+}
+
+impl<D: Clone> ImposterShadowKey for (ImposterKey, D) {
+    fn for_shadow_view(&self) -> Self {
+        (self.0.for_shadow_view(), self.1.clone())
+    }
+}
+
+/// bevy's `queue_shadows`, with the material key marked for the shadow view. the mesh key of a point or
+/// spot light view matches a depth-only camera prepass without msaa, so specialization can't tell them apart
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn queue_imposter_shadows<M: Material>(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    prepass_pipeline: Res<PrepassPipeline<M>>,
+    render_meshes: Res<RenderAssets<RenderMesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    render_materials: Res<RenderAssets<PreparedMaterial<M>>>,
+    render_material_instances: Res<RenderMaterialInstances<M>>,
+    mut shadow_render_phases: ResMut<ViewBinnedRenderPhases<Shadow>>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<PrepassPipeline<M>>>,
+    pipeline_cache: Res<PipelineCache>,
+    view_lights: Query<(Entity, &ViewLightEntities)>,
+    view_light_entities: Query<&LightEntity>,
+    point_light_entities: Query<&RenderCubemapVisibleEntities, With<ExtractedPointLight>>,
+    directional_light_entities: Query<
+        &RenderCascadesVisibleEntities,
+        With<ExtractedDirectionalLight>,
+    >,
+    spot_light_entities: Query<&RenderVisibleMeshEntities, With<ExtractedPointLight>>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone + ImposterShadowKey,
+{
+    let draw_shadow_mesh = shadow_draw_functions.read().id::<DrawPrepass<M>>();
+    for (entity, view_lights) in &view_lights {
+        for view_light_entity in view_lights.lights.iter().copied() {
+            let Ok(light_entity) = view_light_entities.get(view_light_entity) else {
+                continue;
+            };
+            let Some(shadow_phase) = shadow_render_phases.get_mut(&view_light_entity) else {
+                continue;
+            };
+
+            let visible_entities = match light_entity {
+                LightEntity::Directional {
+                    light_entity,
+                    cascade_index,
+                } => directional_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .and_then(|cascades| cascades.entities.get(&entity))
+                    .and_then(|cascades| cascades.get(*cascade_index)),
+                LightEntity::Point {
+                    light_entity,
+                    face_index,
+                } => point_light_entities
+                    .get(*light_entity)
+                    .ok()
+                    .map(|faces| faces.get(*face_index)),
+                LightEntity::Spot { light_entity } => spot_light_entities.get(*light_entity).ok(),
+            };
+            let Some(visible_entities) = visible_entities else {
+                continue;
+            };
+
+            let mut light_key = MeshPipelineKey::DEPTH_PREPASS;
+            light_key.set(
+                MeshPipelineKey::DEPTH_CLAMP_ORTHO,
+                matches!(light_entity, LightEntity::Directional { .. }),
+            );
+
+            for (entity, main_entity) in visible_entities.iter().copied() {
+                let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(main_entity)
+                else {
+                    continue;
+                };
+                if !mesh_instance
+                    .flags
+                    .contains(RenderMeshInstanceFlags::SHADOW_CASTER)
+                {
+                    continue;
+                }
+                let Some(material_asset_id) = render_material_instances.get(&main_entity) else {
+                    continue;
+                };
+                let Some(material) = render_materials.get(*material_asset_id) else {
+                    continue;
+                };
+                let Some(mesh) = render_meshes.get(mesh_instance.mesh_asset_id) else {
+                    continue;
+                };
+
+                // imposters carry their own baked lighting, so unlike bevy we don't check for lightmaps
+                let mut mesh_key =
+                    light_key | MeshPipelineKey::from_bits_retain(mesh.key_bits.bits());
+                if matches!(
+                    material.properties.alpha_mode,
+                    AlphaMode::Mask(_)
+                        | AlphaMode::Blend
+                        | AlphaMode::Premultiplied
+                        | AlphaMode::Add
+                        | AlphaMode::AlphaToCoverage
+                ) {
+                    mesh_key |= MeshPipelineKey::MAY_DISCARD;
+                }
+
+                let pipeline_id = match pipelines.specialize(
+                    &pipeline_cache,
+                    &prepass_pipeline,
+                    MaterialPipelineKey {
+                        mesh_key,
+                        bind_group_data: material.key.for_shadow_view(),
+                    },
+                    &mesh.layout,
+                ) {
+                    Ok(id) => id,
+                    Err(err) => {
+                        error!("{}", err);
+                        continue;
+                    }
+                };
+
+                mesh_instance
+                    .material_bind_group_id
+                    .set(material.get_bind_group_id());
+
+                shadow_phase.add(
+                    ShadowBinKey {
+                        draw_function: draw_shadow_mesh,
+                        pipeline: pipeline_id,
+                        asset_id: mesh_instance.mesh_asset_id.into(),
+                    },
+                    (entity, main_entity),
+                    BinnedRenderPhaseType::mesh(mesh_instance.should_batch()),
+                );
+            }
+        }
+    }
+}
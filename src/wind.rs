@@ -0,0 +1,66 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_resource::BufferUsages,
+        renderer::RenderQueue,
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
+    },
+};
+
+use crate::render::IMPOSTER_WIND_BUFFER_HANDLE;
+
+/// global wind for imposters with wind enabled (see [`ImposterData::with_wind`](crate::ImposterData::with_wind)),
+/// including atlas instances. it's shared by all imposters on the gpu, so it can change every frame.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug)]
+pub struct ImposterWind {
+    /// direction the wind blows towards
+    pub direction: Vec3,
+    /// sway at the top of an imposter, as a fraction of the imposter radius
+    pub strength: f32,
+    /// sway cycles per second
+    pub frequency: f32,
+}
+
+impl Default for ImposterWind {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::X,
+            strength: 0.05,
+            frequency: 0.4,
+        }
+    }
+}
+
+impl ImposterWind {
+    // xyz: direction * strength, w: sway phase in radians at `elapsed_secs`
+    pub(crate) fn as_vec4(&self, elapsed_secs: f64) -> Vec4 {
+        let phase = (elapsed_secs * self.frequency as f64).fract() * std::f64::consts::TAU;
+        (self.direction.normalize_or_zero() * self.strength).extend(phase as f32)
+    }
+}
+
+// the wind is read from one shared buffer, so materials and atlas bind groups never need rebuilding.
+// it's written every frame with the sway phase, so shaders don't depend on the view's globals (which the
+// prepass and shadow views bind differently)
+pub(crate) fn prepare_imposter_wind(
+    wind: Res<ImposterWind>,
+    time: Res<Time>,
+    buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_queue: Res<RenderQueue>,
+) {
+    let Some(buffer) = buffers.get(&IMPOSTER_WIND_BUFFER_HANDLE) else {
+        return;
+    };
+    let value = wind.as_vec4(time.elapsed_secs_f64());
+    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&value));
+}
+
+// the buffer behind `IMPOSTER_WIND_BUFFER_HANDLE`, written from the `ImposterWind` resource
+pub(crate) fn wind_buffer() -> ShaderStorageBuffer {
+    let mut buffer = ShaderStorageBuffer::new(&[0; 16], RenderAssetUsages::RENDER_WORLD);
+    buffer.buffer_description.label = Some("imposter_wind");
+    buffer.buffer_description.usage |= BufferUsages::COPY_DST;
+    buffer
+}